@@ -0,0 +1,363 @@
+use std::{fmt, str::FromStr, sync::Arc};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use md5::{Digest, Md5};
+use sha2::Sha256;
+
+use crate::{
+    oauth2::OAuth2,
+    util::{random_bytes, to_hex},
+    variables::substitute,
+};
+
+/// Credentials of an `auth` key, given in `<connect>` for every query or in a single `<query>`.
+#[derive(Clone)]
+pub enum Auth {
+    /// `auth = none`, a query opting out of the credentials set in `<connect>`.
+    None,
+    Basic {
+        user: String,
+        password: String,
+    },
+    Bearer(String),
+    /// Only sent in answer to a `WWW-Authenticate: Digest` challenge, which costs a retry.
+    Digest {
+        user: String,
+        password: String,
+    },
+    /// Set by an `<oauth2>` block, a bearer token fetched and renewed as needed.
+    OAuth2(Arc<OAuth2>),
+}
+
+impl fmt::Debug for Auth {
+    // Keeps secrets out of debug output
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::None => write!(f, "None"),
+            Auth::Basic { user, .. } => write!(f, "Basic({})", user),
+            Auth::Bearer(_) => write!(f, "Bearer"),
+            Auth::Digest { user, .. } => write!(f, "Digest({})", user),
+            Auth::OAuth2(oauth2) => write!(f, "OAuth2({})", oauth2.client_id),
+        }
+    }
+}
+
+impl FromStr for Auth {
+    type Err = String;
+
+    /// Parses `basic user:pass`, `bearer TOKEN`, `digest user:pass` or `none`, filling in
+    /// `{{NAME}}` variables first.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = substitute(value.trim())?;
+        let (scheme, credentials) = value
+            .split_once(char::is_whitespace)
+            .map(|(scheme, credentials)| (scheme, credentials.trim()))
+            .unwrap_or((value.as_str(), ""));
+        let user_password = || {
+            credentials
+                .split_once(':')
+                .map(|(user, password)| (user.to_string(), password.to_string()))
+                .ok_or(format!(
+                    "Expected `user:password` after `auth = {}`",
+                    scheme
+                ))
+        };
+
+        match scheme.to_ascii_lowercase().as_str() {
+            "none" if credentials.is_empty() => Ok(Auth::None),
+            "basic" => {
+                let (user, password) = user_password()?;
+                Ok(Auth::Basic { user, password })
+            }
+            "digest" => {
+                let (user, password) = user_password()?;
+                Ok(Auth::Digest { user, password })
+            }
+            "bearer" if !credentials.is_empty() => Ok(Auth::Bearer(credentials.to_string())),
+            "bearer" => Err("Expected a token after `auth = bearer`".to_string()),
+            _ => Err(format!(
+                "Invalid auth `{}`, expected basic, bearer, digest or none",
+                scheme
+            )),
+        }
+    }
+}
+
+impl Auth {
+    /// The `Authorization` header to send up front. Digest has none until challenged, and
+    /// OAuth2 none until a token is fetched.
+    pub fn authorization(&self) -> Option<String> {
+        match self {
+            Auth::Basic { user, password } => Some(format!(
+                "Basic {}",
+                STANDARD.encode(format!("{}:{}", user, password))
+            )),
+            Auth::Bearer(token) => Some(format!("Bearer {}", token)),
+            Auth::None | Auth::Digest { .. } | Auth::OAuth2(_) => None,
+        }
+    }
+}
+
+/// The digest challenge among the `WWW-Authenticate` values of a response, SHA-256 preferred.
+pub fn digest_challenge<'a>(values: &[&'a str]) -> Option<&'a str> {
+    let digests: Vec<&str> = values
+        .iter()
+        .copied()
+        .filter(|value| {
+            value
+                .trim_start()
+                .get(..7)
+                .is_some_and(|scheme| scheme.eq_ignore_ascii_case("digest "))
+        })
+        .collect();
+    digests
+        .iter()
+        .find(|challenge| challenge.to_ascii_uppercase().contains("SHA-256"))
+        .or(digests.first())
+        .copied()
+}
+
+/// Splits `key=value, key="quoted, value"` parameters, keys lowercased.
+fn parse_params(text: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = text.trim();
+
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().to_ascii_lowercase();
+        let after = after.trim_start();
+        let (value, remainder) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut end = quoted.len();
+                let mut escaped = false;
+                for (index, ch) in quoted.char_indices() {
+                    if escaped {
+                        value.push(ch);
+                        escaped = false;
+                    } else if ch == '\\' {
+                        escaped = true;
+                    } else if ch == '"' {
+                        end = index + 1;
+                        break;
+                    } else {
+                        value.push(ch);
+                    }
+                }
+                (value, &quoted[end..])
+            }
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            }
+        };
+
+        params.push((key, value));
+        rest = remainder.trim_start().trim_start_matches(',').trim_start();
+    }
+    params
+}
+
+fn md5_hex(text: &str) -> String {
+    to_hex(&Md5::digest(text.as_bytes()))
+}
+
+fn sha256_hex(text: &str) -> String {
+    to_hex(&Sha256::digest(text.as_bytes()))
+}
+
+/// Builds the `Authorization` header answering a digest `challenge` (RFC 7616) for a request
+/// of `method` to `uri`.
+pub fn answer_digest(
+    user: &str,
+    password: &str,
+    challenge: &str,
+    method: &str,
+    uri: &str,
+) -> Result<String, String> {
+    let cnonce = to_hex(&random_bytes::<16>());
+    digest_with_cnonce(user, password, challenge, method, uri, &cnonce)
+}
+
+fn digest_with_cnonce(
+    user: &str,
+    password: &str,
+    challenge: &str,
+    method: &str,
+    uri: &str,
+    cnonce: &str,
+) -> Result<String, String> {
+    let params = parse_params(challenge.trim_start().get(7..).unwrap_or_default());
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let realm = param("realm").unwrap_or_default();
+    let nonce = param("nonce").ok_or("Digest challenge without a nonce")?;
+    let algorithm = param("algorithm").unwrap_or("MD5");
+    let (hash, is_session): (fn(&str) -> String, bool) =
+        match algorithm.to_ascii_uppercase().as_str() {
+            "MD5" => (md5_hex, false),
+            "MD5-SESS" => (md5_hex, true),
+            "SHA-256" => (sha256_hex, false),
+            "SHA-256-SESS" => (sha256_hex, true),
+            other => return Err(format!("Unsupported digest algorithm {}", other)),
+        };
+    // Servers from before RFC 2617 send no qop at all
+    let has_qop = match param("qop") {
+        Some(qop) if !qop.split(',').any(|qop| qop.trim() == "auth") => {
+            return Err(format!("Unsupported digest qop {}", qop));
+        }
+        qop => qop.is_some(),
+    };
+
+    let nc = "00000001";
+    let mut ha1 = hash(&format!("{}:{}:{}", user, realm, password));
+    if is_session {
+        ha1 = hash(&format!("{}:{}:{}", ha1, nonce, cnonce));
+    }
+    let ha2 = hash(&format!("{}:{}", method, uri));
+    let response = if has_qop {
+        hash(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2))
+    } else {
+        hash(&format!("{}:{}:{}", ha1, nonce, ha2))
+    };
+
+    let mut header = format!(
+        "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+        user, realm, nonce, uri, algorithm, response
+    );
+    if has_qop {
+        header.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, cnonce));
+    }
+    if let Some(opaque) = param("opaque") {
+        header.push_str(&format!(", opaque=\"{}\"", opaque));
+    }
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 7616 section 3.9.1
+    const CHALLENGE: &str = "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", \
+        nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+        opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+    const CNONCE: &str = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+    fn answer(algorithm: &str) -> String {
+        let challenge = format!("{}, algorithm={}", CHALLENGE, algorithm);
+        digest_with_cnonce(
+            "Mufasa",
+            "Circle of Life",
+            &challenge,
+            "GET",
+            "/dir/index.html",
+            CNONCE,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_auth_values() {
+        assert!(matches!(
+            "basic ada:secret:1".parse(),
+            Ok(Auth::Basic { user, password }) if user == "ada" && password == "secret:1"
+        ));
+        assert!(matches!(
+            " Bearer  abc.def ".parse(),
+            Ok(Auth::Bearer(token)) if token == "abc.def"
+        ));
+        assert!(matches!("digest a:b".parse(), Ok(Auth::Digest { .. })));
+        assert!(matches!("none".parse(), Ok(Auth::None)));
+        assert_eq!(
+            "basic ada".parse::<Auth>().err(),
+            Some("Expected `user:password` after `auth = basic`".to_string())
+        );
+        assert_eq!(
+            "bearer".parse::<Auth>().err(),
+            Some("Expected a token after `auth = bearer`".to_string())
+        );
+        assert_eq!(
+            "ntlm a:b".parse::<Auth>().err(),
+            Some("Invalid auth `ntlm`, expected basic, bearer, digest or none".to_string())
+        );
+    }
+
+    #[test]
+    fn sends_basic_and_bearer_up_front() {
+        let basic = Auth::Basic {
+            user: "Aladdin".to_string(),
+            password: "open sesame".to_string(),
+        };
+        // RFC 7617, section 2
+        assert_eq!(
+            basic.authorization().as_deref(),
+            Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==")
+        );
+        assert_eq!(
+            Auth::Bearer("t0k".to_string()).authorization().as_deref(),
+            Some("Bearer t0k")
+        );
+        assert!(
+            Auth::Digest {
+                user: "a".to_string(),
+                password: "b".to_string()
+            }
+            .authorization()
+            .is_none()
+        );
+        assert_eq!(format!("{:?}", basic), "Basic(Aladdin)");
+    }
+
+    #[test]
+    fn prefers_the_sha256_digest_challenge() {
+        let md5 = "Digest realm=\"r\", nonce=\"n\"";
+        let sha256 = "digest realm=\"r\", nonce=\"n\", algorithm=SHA-256";
+        assert_eq!(
+            digest_challenge(&["Basic realm=\"r\"", md5, sha256]),
+            Some(sha256)
+        );
+        assert_eq!(digest_challenge(&[md5]), Some(md5));
+        assert_eq!(digest_challenge(&["Bearer"]), None);
+    }
+
+    #[test]
+    fn answers_rfc_7616_md5_example() {
+        assert_eq!(
+            answer("MD5"),
+            "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+            nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", uri=\"/dir/index.html\", \
+            algorithm=MD5, response=\"8ca523f5e9506fed4657c9700eebdbec\", qop=auth, nc=00000001, \
+            cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", \
+            opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""
+        );
+    }
+
+    #[test]
+    fn answers_rfc_7616_sha256_example() {
+        assert!(answer("SHA-256").contains(
+            "response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""
+        ));
+    }
+
+    #[test]
+    fn rejects_unsupported_challenges() {
+        let challenge = format!("{}, algorithm=SHA-512-256", CHALLENGE);
+        assert!(answer_digest("a", "b", &challenge, "GET", "/").is_err());
+        assert!(
+            answer_digest(
+                "a",
+                "b",
+                "Digest realm=\"r\", qop=\"auth-int\", nonce=\"n\"",
+                "GET",
+                "/"
+            )
+            .is_err()
+        );
+        assert!(answer_digest("a", "b", "Digest realm=\"r\"", "GET", "/").is_err());
+    }
+}
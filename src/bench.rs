@@ -0,0 +1,356 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    http::Request,
+    network::{Connection, open_stream, send_request},
+};
+
+const HISTOGRAM_BUCKETS: usize = 10;
+const HISTOGRAM_WIDTH: usize = 40;
+/// Consecutive failed connects after which a worker gives up on an unreachable server.
+const MAX_CONNECT_FAILURES: u32 = 5;
+const CONNECT_BACKOFF: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy)]
+pub enum BenchLimit {
+    Duration(Duration),
+    Requests(usize),
+}
+
+impl Default for BenchLimit {
+    fn default() -> Self {
+        BenchLimit::Duration(Duration::from_secs(10))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BenchOptions {
+    pub concurrency: usize,
+    pub limit: BenchLimit,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            concurrency: 1,
+            limit: BenchLimit::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Samples {
+    latencies: Vec<Duration>,
+    statuses: BTreeMap<u16, usize>,
+    errors: BTreeMap<String, usize>,
+}
+
+impl Samples {
+    fn merge(&mut self, other: Samples) {
+        self.latencies.extend(other.latencies);
+        for (status, count) in other.statuses {
+            *self.statuses.entry(status).or_default() += count;
+        }
+        for (error, count) in other.errors {
+            *self.errors.entry(error).or_default() += count;
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.statuses.values().sum::<usize>() + self.errors.values().sum::<usize>()
+    }
+}
+
+/// Replays `requests` round robin from `concurrency` connections until the limit is hit.
+pub fn run_bench(requests: Vec<Request>, conn: &Connection, options: BenchOptions) {
+    if requests.is_empty() {
+        println!("⚠️  No queries to bench");
+        return;
+    }
+
+    println!(
+        "\n🏎️  Benching {} query(s) with {} connection(s), {}\n",
+        requests.len(),
+        options.concurrency,
+        match options.limit {
+            BenchLimit::Duration(duration) => format!("for {:?}", duration),
+            BenchLimit::Requests(count) => format!("{} request(s)", count),
+        }
+    );
+
+    let requests = Arc::new(requests);
+    let counter = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+
+    let workers: Vec<_> = (0..options.concurrency)
+        .map(|_| {
+            let requests = Arc::clone(&requests);
+            let counter = Arc::clone(&counter);
+            let mut worker_conn = conn.detached();
+            thread::spawn(move || bench_worker(&requests, &mut worker_conn, &counter, options))
+        })
+        .collect();
+
+    let mut samples = Samples::default();
+    for worker in workers {
+        if let Ok(worker_samples) = worker.join() {
+            samples.merge(worker_samples);
+        }
+    }
+
+    print_bench_report(&mut samples, started.elapsed());
+}
+
+fn bench_worker(
+    requests: &[Request],
+    conn: &mut Connection,
+    counter: &AtomicUsize,
+    options: BenchOptions,
+) -> Samples {
+    let mut samples = Samples::default();
+    let started = Instant::now();
+    let mut connect_failures = 0;
+
+    loop {
+        let sequence = counter.fetch_add(1, Ordering::Relaxed);
+        match options.limit {
+            BenchLimit::Duration(duration) if started.elapsed() >= duration => break,
+            BenchLimit::Requests(count) if sequence >= count => break,
+            _ => {}
+        }
+
+        if !conn.is_open() {
+            if let Err(err) = open_stream(conn) {
+                *samples.errors.entry(err.to_string()).or_default() += 1;
+                connect_failures += 1;
+                if connect_failures >= MAX_CONNECT_FAILURES {
+                    break;
+                }
+                thread::sleep(CONNECT_BACKOFF * 2u32.pow(connect_failures - 1));
+                continue;
+            }
+            connect_failures = 0;
+        }
+
+        let request = &requests[sequence % requests.len()];
+        let sent_at = Instant::now();
+        match send_request(conn, request) {
+            Ok(response) => {
+                samples.latencies.push(sent_at.elapsed());
+                *samples.statuses.entry(response.status).or_default() += 1;
+            }
+            Err(err) => {
+                *samples.errors.entry(err).or_default() += 1;
+                conn.reader = None;
+            }
+        }
+    }
+
+    samples
+}
+
+fn percentile(sorted: &[Duration], pct: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Upper bound and sample count of equally wide buckets spanning the sorted latencies.
+fn histogram(sorted: &[Duration]) -> Vec<(Duration, usize)> {
+    let (Some(&min), Some(&max)) = (sorted.first(), sorted.last()) else {
+        return Vec::new();
+    };
+
+    let span = (max - min).max(Duration::from_micros(1));
+    let mut buckets = [0usize; HISTOGRAM_BUCKETS];
+    for latency in sorted {
+        // Whole nanoseconds keep a latency on a bound in the bucket that bound closes
+        let offset = (*latency - min).as_nanos() * HISTOGRAM_BUCKETS as u128;
+        let position = offset.div_ceil(span.as_nanos()).max(1) as usize - 1;
+        buckets[position.min(HISTOGRAM_BUCKETS - 1)] += 1;
+    }
+
+    buckets
+        .into_iter()
+        .enumerate()
+        .map(|(index, count)| {
+            let upper = min + span * (index + 1) as u32 / HISTOGRAM_BUCKETS as u32;
+            (upper, count)
+        })
+        .collect()
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn print_bench_report(samples: &mut Samples, elapsed: Duration) {
+    samples.latencies.sort();
+    let total = samples.total();
+    let errors: usize = samples.errors.values().sum();
+
+    println!("Report:");
+    println!("  Requests:    {}", total);
+    println!("  Elapsed:     {:.2}s", elapsed.as_secs_f64());
+    println!(
+        "  Throughput:  {:.2} req/s",
+        total as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+    );
+    println!(
+        "  Error rate:  {:.2}%",
+        if total == 0 {
+            0.0
+        } else {
+            errors as f64 * 100.0 / total as f64
+        }
+    );
+
+    println!("\nStatus codes:");
+    for (status, count) in &samples.statuses {
+        println!("  {}: {}", status, count);
+    }
+    for (error, count) in &samples.errors {
+        println!("  💥 {}: {}", error, count);
+    }
+
+    if samples.latencies.is_empty() {
+        return;
+    }
+
+    println!("\nLatency:");
+    for (label, pct) in [("p50", 50.0), ("p90", 90.0), ("p99", 99.0)] {
+        println!(
+            "  {}: {:.2}ms",
+            label,
+            as_millis(percentile(&samples.latencies, pct))
+        );
+    }
+    let max = samples.latencies[samples.latencies.len() - 1];
+    println!("  max: {:.2}ms", as_millis(max));
+
+    println!("\nHistogram:");
+    let buckets = histogram(&samples.latencies);
+    let tallest = buckets
+        .iter()
+        .map(|&(_, count)| count)
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    for (upper, count) in buckets {
+        println!(
+            "  <= {:>9.2}ms | {:<width$} {}",
+            as_millis(upper),
+            "█".repeat(count * HISTOGRAM_WIDTH / tallest),
+            count,
+            width = HISTOGRAM_WIDTH
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, sync::Arc};
+
+    use super::*;
+    use crate::{http::Timings, network::Protocol};
+
+    fn millis(values: &[u64]) -> Vec<Duration> {
+        values.iter().map(|&ms| Duration::from_millis(ms)).collect()
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let latencies = millis(&(1..=100).collect::<Vec<u64>>());
+        assert_eq!(percentile(&latencies, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&latencies, 90.0), Duration::from_millis(90));
+        assert_eq!(percentile(&latencies, 99.0), Duration::from_millis(99));
+
+        let latencies = millis(&[10, 20, 30]);
+        assert_eq!(percentile(&latencies, 50.0), Duration::from_millis(20));
+        assert_eq!(percentile(&latencies, 99.0), Duration::from_millis(30));
+        assert_eq!(percentile(&latencies, 0.0), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn percentiles_of_few_samples() {
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+        for pct in [50.0, 90.0, 99.0] {
+            assert_eq!(percentile(&millis(&[7]), pct), Duration::from_millis(7));
+        }
+    }
+
+    #[test]
+    fn histogram_buckets_span_min_to_max() {
+        let buckets = histogram(&millis(&[10, 10, 14, 19, 20, 110]));
+        assert_eq!(buckets.len(), HISTOGRAM_BUCKETS);
+        let uppers: Vec<u64> = buckets
+            .iter()
+            .map(|(upper, _)| upper.as_millis() as u64)
+            .collect();
+        assert_eq!(uppers, [20, 30, 40, 50, 60, 70, 80, 90, 100, 110]);
+        let counts: Vec<usize> = buckets.iter().map(|&(_, count)| count).collect();
+        // Latencies on an upper bound count towards the bucket it closes
+        assert_eq!(counts, [5, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let counts: Vec<usize> = histogram(&millis(&[0, 10, 11, 90, 91, 100]))
+            .iter()
+            .map(|&(_, count)| count)
+            .collect();
+        assert_eq!(counts, [2, 1, 0, 0, 0, 0, 0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn histogram_of_equal_or_no_samples() {
+        assert!(histogram(&[]).is_empty());
+        let counts: Vec<usize> = histogram(&millis(&[5, 5, 5]))
+            .iter()
+            .map(|&(_, count)| count)
+            .collect();
+        assert_eq!(counts, [3, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn workers_give_up_on_an_unreachable_server() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut conn = Connection {
+            host: "127.0.0.1".to_string(),
+            port,
+            is_safe: false,
+            protocol: Protocol::default(),
+            auth: None,
+            reader: None,
+            h2: Arc::default(),
+            handshake: Timings::default(),
+            cookies: Arc::default(),
+        };
+        let options = BenchOptions {
+            concurrency: 1,
+            limit: BenchLimit::Duration(Duration::from_secs(60)),
+        };
+
+        let started = Instant::now();
+        let samples = bench_worker(
+            &[Request::default()],
+            &mut conn,
+            &AtomicUsize::new(0),
+            options,
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(samples.total(), MAX_CONNECT_FAILURES as usize);
+        assert!(samples.latencies.is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+use crate::{
+    bench::{BenchLimit, BenchOptions},
+    report::RunPolicy,
+};
+
+#[derive(Default, Debug)]
+pub enum Command {
+    #[default]
+    Run,
+    Bench(BenchOptions),
+}
+
+#[derive(Default, Debug)]
+pub struct Options {
+    pub command: Command,
+    pub file_path: Option<String>,
+    pub policy: RunPolicy,
+    pub jobs: usize,
+    pub update_snapshots: bool,
+}
+
+pub fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        jobs: 1,
+        ..Default::default()
+    };
+
+    let mut args = args.iter().peekable();
+    if args.peek().is_some_and(|arg| arg.as_str() == "bench") {
+        args.next();
+        options.command = Command::Bench(BenchOptions::default());
+    }
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if arg.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next().cloned())
+                .ok_or(format!("{} expects a value", flag))
+        };
+
+        match (flag, &mut options.command) {
+            ("--fail-fast", _) => options.policy = RunPolicy::FailFast,
+            ("--update-snapshots", _) => options.update_snapshots = true,
+            ("--jobs" | "-j", _) => options.jobs = parse_count(flag, &value()?)?,
+            ("--concurrency" | "-c", Command::Bench(bench)) => {
+                bench.concurrency = parse_count(flag, &value()?)?
+            }
+            ("--duration" | "-d", Command::Bench(bench)) => {
+                bench.limit = BenchLimit::Duration(parse_duration(&value()?)?)
+            }
+            ("--requests" | "-n", Command::Bench(bench)) => {
+                bench.limit = BenchLimit::Requests(parse_count(flag, &value()?)?)
+            }
+            (flag, _) if flag.starts_with('-') => return Err(format!("Unknown flag: {}", flag)),
+            (path, _) => {
+                if options.file_path.is_some() {
+                    return Err(format!("Unexpected argument: {}", path));
+                }
+                options.file_path = Some(path.to_string());
+            }
+        }
+    }
+
+    Ok(options)
+}
+
+pub fn parse_count(flag: &str, value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("Invalid value for {}: {}", flag, value)),
+    }
+}
+
+/// Accepts `500ms`, `30s`, `2m` or a bare number of seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration: {}", value);
+    let (number, unit) = match value.find(|ch: char| !ch.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let number = number.parse::<u64>().map_err(|_| invalid())?;
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        "m" => number
+            .checked_mul(60)
+            .map(Duration::from_secs)
+            .ok_or_else(invalid),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn continues_past_failures_unless_told_otherwise() {
+        let options = parse(&["api.hop"]).unwrap();
+        assert_eq!(options.policy, RunPolicy::Continue);
+        assert_eq!(options.file_path.as_deref(), Some("api.hop"));
+        assert_eq!(
+            parse(&["--fail-fast", "api.hop"]).unwrap().policy,
+            RunPolicy::FailFast
+        );
+        assert_eq!(parse(&["--fast"]).unwrap_err(), "Unknown flag: --fast");
+        assert_eq!(
+            parse(&["a.hop", "b.hop"]).unwrap_err(),
+            "Unexpected argument: b.hop"
+        );
+    }
+
+    #[test]
+    fn jobs_take_a_positive_count() {
+        assert_eq!(parse(&["api.hop"]).unwrap().jobs, 1);
+        assert_eq!(parse(&["-j", "8", "api.hop"]).unwrap().jobs, 8);
+        assert_eq!(parse(&["--jobs=4"]).unwrap().jobs, 4);
+        assert_eq!(
+            parse(&["--jobs", "0"]).unwrap_err(),
+            "Invalid value for --jobs: 0"
+        );
+        assert_eq!(parse(&["-j"]).unwrap_err(), "-j expects a value");
+    }
+
+    #[test]
+    fn bench_flags_only_follow_bench() {
+        let options = parse(&["bench", "-c", "4", "--duration=30s", "api.hop"]).unwrap();
+        let Command::Bench(bench) = options.command else {
+            panic!("expected the bench command");
+        };
+        assert_eq!(bench.concurrency, 4);
+        assert!(matches!(bench.limit, BenchLimit::Duration(d) if d == Duration::from_secs(30)));
+
+        let options = parse(&["bench", "-n", "100"]).unwrap();
+        assert!(matches!(
+            options.command,
+            Command::Bench(BenchOptions {
+                limit: BenchLimit::Requests(100),
+                ..
+            })
+        ));
+        assert_eq!(
+            parse(&["-c", "4", "api.hop"]).unwrap_err(),
+            "Unknown flag: -c"
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("15"), Ok(Duration::from_secs(15)));
+        for invalid in ["", "s", "1h", "1.5s", "-1s", "18446744073709551615m"] {
+            assert_eq!(
+                parse_duration(invalid),
+                Err(format!("Invalid duration: {}", invalid))
+            );
+        }
+    }
+}
@@ -11,7 +11,9 @@ impl Lexer {
             '{' | '}' | '(' | ')' => Token::Punctuation(ch),
             '=' | '>' | '<' | '~' | '^' => Token::Operator(ch),
             '"' => Token::StringLiteral(self.read_string()),
-            ch if ch.is_ascii_digit() => Token::IntegerLiteral(self.read_number()),
+            ch if ch.is_ascii_digit() => self
+                .read_number()
+                .map_or(Token::Error, Token::IntegerLiteral),
             ch if self.is_ident_start(ch) => Token::Identifier(self.read_identifier()),
             _ => Token::Error,
         }
@@ -55,7 +57,8 @@ impl Lexer {
         value
     }
 
-    fn read_number(&mut self) -> u32 {
+    /// `None` for numbers too large for a `u32`.
+    fn read_number(&mut self) -> Option<u32> {
         let mut num = String::new();
 
         while let Some(ch) = self.peek() {
@@ -67,7 +70,7 @@ impl Lexer {
             }
         }
 
-        num.parse::<u32>().unwrap()
+        num.parse::<u32>().ok()
     }
 
     fn read_string(&mut self) -> String {
@@ -102,3 +105,29 @@ impl Lexer {
         self.is_ident_start(ch) || ch.is_ascii_digit()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<Token> {
+        Lexer {
+            input: input.to_string(),
+            position: 0,
+        }
+        .tokenize()
+    }
+
+    #[test]
+    fn numbers_beyond_u32_are_errors() {
+        assert_eq!(
+            tokens("x = 42"),
+            [
+                Token::Identifier("x".to_string()),
+                Token::Operator('='),
+                Token::IntegerLiteral(42)
+            ]
+        );
+        assert_eq!(tokens("x = 4294967296")[2], Token::Error);
+    }
+}
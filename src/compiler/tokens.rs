@@ -3,6 +3,7 @@ pub enum Token {
     Identifier(String),
     IntegerLiteral(u32),
     StringLiteral(String),
+    #[allow(dead_code)]
     Keyword(String),
     Operator(char),
     Punctuation(char),
@@ -0,0 +1,497 @@
+use std::{
+    cmp::Reverse,
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    /// Set when the server sent no `Domain`, the cookie then only goes back to the exact host.
+    pub host_only: bool,
+    pub path: String,
+    /// Unix timestamp, `None` for session cookies.
+    pub expires: Option<u64>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn matches_domain(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        if self.host_only {
+            return host == self.domain;
+        }
+
+        host == self.domain || host.ends_with(&format!(".{}", self.domain))
+    }
+
+    fn matches_path(&self, path: &str) -> bool {
+        path == self.path
+            || (path.starts_with(&self.path)
+                && (self.path.ends_with('/') || path[self.path.len()..].starts_with('/')))
+    }
+}
+
+/// Cookies collected from `Set-Cookie` headers, sent back on matching requests.
+#[derive(Default, Debug)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+    /// Netscape cookie file the jar is saved to between runs.
+    pub file: Option<PathBuf>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl CookieJar {
+    /// Opens a jar backed by `file`, starting empty when the file does not exist yet.
+    pub fn open(file: PathBuf) -> Result<CookieJar, String> {
+        let mut jar = CookieJar {
+            cookies: Vec::new(),
+            file: None,
+        };
+
+        if file.exists() {
+            let content = fs::read_to_string(&file)
+                .map_err(|e| format!("Failed to read cookie jar {}: {}", file.display(), e))?;
+            for line in content.lines() {
+                if let Some(cookie) = parse_netscape_line(line) {
+                    jar.insert(cookie);
+                }
+            }
+        }
+
+        jar.file = Some(file);
+        Ok(jar)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+
+        let now = now();
+        let mut content = String::from("# Netscape HTTP Cookie File\n");
+        for cookie in self.cookies.iter().filter(|c| !c.is_expired(now)) {
+            content.push_str(&format!(
+                "{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                if cookie.http_only { "#HttpOnly_" } else { "" },
+                if cookie.host_only {
+                    cookie.domain.clone()
+                } else {
+                    format!(".{}", cookie.domain)
+                },
+                if cookie.host_only { "FALSE" } else { "TRUE" },
+                cookie.path,
+                if cookie.secure { "TRUE" } else { "FALSE" },
+                cookie.expires.unwrap_or(0),
+                cookie.name,
+                cookie.value
+            ));
+        }
+
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        fs::write(file, content)
+            .map_err(|e| format!("Failed to write cookie jar {}: {}", file.display(), e))
+    }
+
+    fn insert(&mut self, cookie: Cookie) {
+        self.cookies.retain(|c| {
+            !(c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path)
+        });
+
+        if !cookie.is_expired(now()) {
+            self.cookies.push(cookie);
+        }
+    }
+
+    /// Stores a `Set-Cookie` header received for a request to `host` and `url`.
+    pub fn store(&mut self, host: &str, url: &str, set_cookie: &str) {
+        let mut parts = set_cookie.split(';');
+        let Some((name, value)) = parts.next().and_then(|pair| pair.split_once('=')) else {
+            return;
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return;
+        }
+
+        let host = host.to_lowercase();
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            expires: None,
+            secure: false,
+            http_only: false,
+        };
+        let mut max_age = None;
+
+        for attribute in parts {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().to_lowercase().as_str() {
+                "domain" => {
+                    let domain = value.trim_start_matches('.').to_lowercase();
+                    if domain.is_empty() {
+                        continue;
+                    }
+                    // A server may only set cookies for itself or a parent domain
+                    if host != domain && !host.ends_with(&format!(".{}", domain)) {
+                        return;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "expires" => {
+                    if let Some(expires) = parse_http_date(value) {
+                        cookie.expires = Some(expires);
+                    }
+                }
+                "max-age" => max_age = value.parse::<i64>().ok(),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                _ => {}
+            }
+        }
+
+        // Max-Age wins over Expires, zero or negative deletes the cookie
+        if let Some(max_age) = max_age {
+            cookie.expires = Some(if max_age <= 0 {
+                0
+            } else {
+                now().saturating_add(max_age as u64)
+            });
+        }
+
+        self.insert(cookie);
+    }
+
+    /// Builds the `Cookie` header value for a request, longest paths first.
+    pub fn header_for(&self, host: &str, url: &str, is_secure: bool) -> Option<String> {
+        let path = url.split(['?', '#']).next().unwrap_or("/");
+        let now = now();
+
+        let mut matching: Vec<&Cookie> = self
+            .cookies
+            .iter()
+            .filter(|c| !c.is_expired(now))
+            .filter(|c| c.matches_domain(host) && c.matches_path(path))
+            .filter(|c| is_secure || !c.secure)
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+
+        matching.sort_by_key(|c| Reverse(c.path.len()));
+        Some(
+            matching
+                .iter()
+                .map(|c| format!("{}={}", c.name, c.value))
+                .collect::<Vec<String>>()
+                .join("; "),
+        )
+    }
+}
+
+/// The directory of the request path, used when `Set-Cookie` has no `Path`.
+fn default_path(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    match path.rfind('/') {
+        Some(0) | None => String::from("/"),
+        Some(index) => path[..index].to_string(),
+    }
+}
+
+fn parse_netscape_line(line: &str) -> Option<Cookie> {
+    let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+        Some(line) => (line, true),
+        None if line.starts_with('#') => return None,
+        None => (line, false),
+    };
+
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 7 {
+        return None;
+    }
+
+    let expires = fields[4].parse::<u64>().ok()?;
+    Some(Cookie {
+        name: fields[5].to_string(),
+        value: fields[6].to_string(),
+        domain: fields[0].trim_start_matches('.').to_lowercase(),
+        host_only: fields[1] != "TRUE",
+        path: fields[2].to_string(),
+        expires: if expires == 0 { None } else { Some(expires) },
+        secure: fields[3] == "TRUE",
+        http_only,
+    })
+}
+
+/// Parses `Wed, 21 Oct 2015 07:28:00 GMT` and the older `21-Oct-15` style into a Unix timestamp.
+fn parse_http_date(value: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+
+    let (mut day, mut month, mut year, mut time) = (None, None, None, None);
+    for token in value.split([' ', ',', '-']).filter(|t| !t.is_empty()) {
+        let lower = token.to_lowercase();
+        if time.is_none() && token.contains(':') {
+            let parts: Vec<u64> = token.split(':').filter_map(|p| p.parse().ok()).collect();
+            if let [hours @ 0..=23, minutes @ 0..=59, seconds @ 0..=59] = parts[..] {
+                time = Some(hours * 3600 + minutes * 60 + seconds);
+            }
+        } else if month.is_none()
+            && let Some(index) = MONTHS.iter().position(|m| lower.starts_with(m))
+        {
+            month = Some(index as u64 + 1);
+        } else if let Ok(number) = token.parse::<u64>() {
+            if day.is_none() && token.len() <= 2 && (1..=31).contains(&number) {
+                day = Some(number);
+            } else if year.is_none() {
+                year = Some(match number {
+                    0..=69 => number + 2000,
+                    70..=99 => number + 1900,
+                    _ => number,
+                });
+            }
+        }
+    }
+
+    let (day, month, year, time) = (day?, month?, year?, time?);
+    if year < 1970 {
+        return Some(0);
+    }
+    // Later dates are as good as never, capping keeps the arithmetic below in range
+    let year = year.min(9999);
+
+    // Days since the epoch for a proleptic Gregorian date
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let year_of_era = y % 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    Some(days * 86400 + time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_http_dates() {
+        assert_eq!(
+            parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(1445412480)
+        );
+        assert_eq!(
+            parse_http_date("Wednesday, 21-Oct-15 07:28:00 GMT"),
+            Some(1445412480)
+        );
+        assert_eq!(parse_http_date("Thu, 01 Jan 1950 00:00:00 GMT"), Some(0));
+    }
+
+    #[test]
+    fn caps_far_future_years() {
+        assert_eq!(
+            parse_http_date("Fri, 31 Dec 9999 23:59:59 GMT"),
+            Some(253402300799)
+        );
+        assert_eq!(
+            parse_http_date("Wed, 31 Dec 99999999999999 23:59:59 GMT"),
+            Some(253402300799)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        assert_eq!(parse_http_date("Wed, 21 Oct 2015 24:00:00 GMT"), None);
+        assert_eq!(
+            parse_http_date("Wed, 21 Oct 2015 99999999999999999:00:00 GMT"),
+            None
+        );
+        assert_eq!(parse_http_date("Wed, 21 Oct 2015 GMT"), None);
+    }
+
+    fn jar(host: &str, url: &str, set_cookies: &[&str]) -> CookieJar {
+        let mut jar = CookieJar::default();
+        for set_cookie in set_cookies {
+            jar.store(host, url, set_cookie);
+        }
+        jar
+    }
+
+    #[test]
+    fn domain_attribute_must_cover_the_host() {
+        let jar = jar(
+            "api.example.com",
+            "/",
+            &[
+                "parent=1; Domain=.example.com",
+                "foreign=1; Domain=other.com",
+            ],
+        );
+        assert_eq!(jar.cookies.len(), 1);
+        assert_eq!(jar.cookies[0].domain, "example.com");
+        assert!(!jar.cookies[0].host_only);
+        assert_eq!(
+            jar.header_for("www.example.com", "/", false),
+            Some("parent=1".to_string())
+        );
+        assert_eq!(jar.header_for("other.com", "/", false), None);
+    }
+
+    #[test]
+    fn host_only_cookies_skip_subdomains() {
+        let jar = jar("example.com", "/", &["id=1"]);
+        assert_eq!(
+            jar.header_for("EXAMPLE.com", "/", false),
+            Some("id=1".to_string())
+        );
+        assert_eq!(jar.header_for("www.example.com", "/", false), None);
+        assert_eq!(jar.header_for("badexample.com", "/", false), None);
+    }
+
+    #[test]
+    fn defaults_the_path_to_the_request_directory() {
+        assert_eq!(default_path("/a/b/c?x=/y"), "/a/b");
+        assert_eq!(default_path("/a"), "/");
+        assert_eq!(default_path(""), "/");
+
+        let jar = jar("example.com", "/docs/page", &["id=1"]);
+        assert_eq!(jar.cookies[0].path, "/docs");
+        assert!(
+            jar.header_for("example.com", "/docs/other", false)
+                .is_some()
+        );
+        assert!(jar.header_for("example.com", "/", false).is_none());
+    }
+
+    #[test]
+    fn paths_match_on_segment_boundaries() {
+        let jar = jar("example.com", "/", &["id=1; Path=/foo"]);
+        for path in ["/foo", "/foo/", "/foo/bar", "/foo?q=1"] {
+            assert!(
+                jar.header_for("example.com", path, false).is_some(),
+                "{}",
+                path
+            );
+        }
+        for path in ["/foobar", "/fo", "/"] {
+            assert!(
+                jar.header_for("example.com", path, false).is_none(),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn secure_cookies_need_a_secure_request() {
+        let jar = jar("example.com", "/", &["plain=1", "secret=2; Secure"]);
+        assert_eq!(
+            jar.header_for("example.com", "/", false),
+            Some("plain=1".to_string())
+        );
+        assert_eq!(
+            jar.header_for("example.com", "/", true),
+            Some("plain=1; secret=2".to_string())
+        );
+    }
+
+    #[test]
+    fn sends_longest_paths_first() {
+        let jar = jar(
+            "example.com",
+            "/",
+            &["root=1; Path=/", "deep=3; Path=/a/b", "mid=2; Path=/a"],
+        );
+        assert_eq!(
+            jar.header_for("example.com", "/a/b/c", false),
+            Some("deep=3; mid=2; root=1".to_string())
+        );
+    }
+
+    #[test]
+    fn max_age_expires_and_deletes() {
+        let mut jar = jar(
+            "example.com",
+            "/",
+            &["id=1; Max-Age=3600; Expires=Thu, 01 Jan 1970 00:00:01 GMT"],
+        );
+        let expires = jar.cookies[0].expires.unwrap();
+        assert!(expires > now() + 3500 && expires <= now() + 3600);
+
+        jar.store("example.com", "/", "id=; Max-Age=0");
+        assert!(jar.cookies.is_empty());
+        jar.store("example.com", "/", "id=1; Max-Age=-1");
+        assert!(jar.cookies.is_empty());
+        jar.store(
+            "example.com",
+            "/",
+            "id=1; Expires=Thu, 01 Jan 1970 00:00:01 GMT",
+        );
+        assert!(jar.cookies.is_empty());
+    }
+
+    #[test]
+    fn saves_and_opens_netscape_files() {
+        let file = std::env::temp_dir().join(format!("nethop-{}-cookies.txt", std::process::id()));
+        let _ = fs::remove_file(&file);
+
+        let mut jar = CookieJar::open(file.clone()).unwrap();
+        jar.store("api.example.com", "/", "session=abc; HttpOnly");
+        jar.store(
+            "api.example.com",
+            "/",
+            "token=xyz; Domain=example.com; Path=/v1; Secure; Max-Age=3600",
+        );
+        jar.save().unwrap();
+
+        let content = fs::read_to_string(&file).unwrap();
+        assert!(content.starts_with("# Netscape HTTP Cookie File\n"));
+        assert!(content.contains("#HttpOnly_api.example.com\tFALSE\t/\tFALSE\t0\tsession\tabc\n"));
+        assert!(content.contains(".example.com\tTRUE\t/v1\tTRUE\t"));
+
+        let reopened = CookieJar::open(file.clone()).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert_eq!(reopened.cookies.len(), 2);
+        let session = &reopened.cookies[0];
+        assert!(session.http_only && session.host_only && session.expires.is_none());
+        let token = &reopened.cookies[1];
+        assert!(token.secure && !token.host_only && token.expires.is_some());
+        assert_eq!(
+            reopened.header_for("www.example.com", "/v1/users", true),
+            Some("token=xyz".to_string())
+        );
+        assert_eq!(
+            reopened.header_for("api.example.com", "/v1/users", true),
+            Some("token=xyz; session=abc".to_string())
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use serde_json::Value;
+
+use crate::schema::join_pointer;
+
+const CONTEXT_LINES: usize = 2;
+/// Above this many cells the LCS table gets too large, so the texts are shown whole instead.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Debug, PartialEq)]
+enum Edit<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Line diff of `expected` against `actual`, `-` lines being expected and `+` lines actual.
+/// Unchanged lines far from any change are collapsed.
+pub fn line_diff(expected: &str, actual: &str) -> Vec<String> {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+
+    let edits = if old.len() * new.len() > MAX_DIFF_CELLS {
+        old.iter()
+            .map(|line| Edit::Removed(line))
+            .chain(new.iter().map(|line| Edit::Added(line)))
+            .collect()
+    } else {
+        diff_lines(&old, &new)
+    };
+
+    let is_change = |edit: &Edit| !matches!(edit, Edit::Same(_));
+    let mut output = Vec::new();
+    let mut skipped = 0;
+
+    for (index, edit) in edits.iter().enumerate() {
+        let window =
+            index.saturating_sub(CONTEXT_LINES)..(index + CONTEXT_LINES + 1).min(edits.len());
+        let near_change = edits[window].iter().any(is_change);
+
+        match edit {
+            Edit::Same(_) if !near_change => {
+                skipped += 1;
+                continue;
+            }
+            _ if skipped > 0 => {
+                output.push(format!("  ... {} unchanged line(s)", skipped));
+                skipped = 0;
+            }
+            _ => {}
+        }
+
+        output.push(match edit {
+            Edit::Same(line) => format!("  {}", line),
+            Edit::Removed(line) => format!("- {}", line),
+            Edit::Added(line) => format!("+ {}", line),
+        });
+    }
+
+    if skipped > 0 && !output.is_empty() {
+        output.push(format!("  ... {} unchanged line(s)", skipped));
+    }
+
+    output
+}
+
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Edit<'a>> {
+    // lcs[i][j] holds the longest common subsequence length of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = Vec::with_capacity(old.len().max(new.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            edits.push(Edit::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            edits.push(Edit::Removed(old[i]));
+            i += 1;
+        } else {
+            edits.push(Edit::Added(new[j]));
+            j += 1;
+        }
+    }
+    edits.extend(old[i..].iter().map(|line| Edit::Removed(line)));
+    edits.extend(new[j..].iter().map(|line| Edit::Added(line)));
+
+    edits
+}
+
+/// Structural diff of two JSON values, one line per differing JSON pointer.
+pub fn json_diff(expected: &Value, actual: &Value) -> Vec<String> {
+    let mut output = Vec::new();
+    diff_values("", expected, actual, &mut output);
+    output
+}
+
+fn diff_values(pointer: &str, expected: &Value, actual: &Value, out: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, expected_value) in expected {
+                let child = join_pointer(pointer, key);
+                match actual.get(key) {
+                    Some(actual_value) => diff_values(&child, expected_value, actual_value, out),
+                    None => out.push(format!("- #{}: {}", child, expected_value)),
+                }
+            }
+            for (key, actual_value) in actual {
+                if !expected.contains_key(key) {
+                    out.push(format!(
+                        "+ #{}: {}",
+                        join_pointer(pointer, key),
+                        actual_value
+                    ));
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for index in 0..expected.len().max(actual.len()) {
+                let child = join_pointer(pointer, &index.to_string());
+                match (expected.get(index), actual.get(index)) {
+                    (Some(e), Some(a)) => diff_values(&child, e, a, out),
+                    (Some(e), None) => out.push(format!("- #{}: {}", child, e)),
+                    (None, Some(a)) => out.push(format!("+ #{}: {}", child, a)),
+                    (None, None) => {}
+                }
+            }
+        }
+        _ if expected != actual => {
+            out.push(format!(
+                "~ #{}: expected {}, found {}",
+                pointer, expected, actual
+            ));
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn line_diff_marks_changes() {
+        assert_eq!(
+            line_diff("a\nb\nc", "a\nx\nc"),
+            ["  a", "- b", "+ x", "  c"]
+        );
+        assert!(line_diff("same\ntext", "same\ntext").is_empty());
+    }
+
+    #[test]
+    fn line_diff_collapses_far_lines() {
+        let expected: Vec<String> = (1..=10).map(|n| n.to_string()).collect();
+        let mut actual = expected.clone();
+        actual[9] = String::from("ten");
+        assert_eq!(
+            line_diff(&expected.join("\n"), &actual.join("\n")),
+            ["  ... 7 unchanged line(s)", "  8", "  9", "- 10", "+ ten"]
+        );
+    }
+
+    #[test]
+    fn json_diff_reports_pointers() {
+        assert_eq!(
+            json_diff(
+                &json!({"a": 1, "b": [1, 2], "c": "x", "d/e": true}),
+                &json!({"a": 2, "b": [1], "d/e": true, "f": null})
+            ),
+            [
+                "~ #/a: expected 1, found 2",
+                "- #/b/1: 2",
+                "- #/c: \"x\"",
+                "+ #/f: null"
+            ]
+        );
+        assert_eq!(
+            json_diff(&json!(1), &json!("1")),
+            ["~ #: expected 1, found \"1\""]
+        );
+        assert_eq!(
+            json_diff(&json!({"a/b": 1}), &json!({"a/b": 2})),
+            ["~ #/a~1b: expected 1, found 2"]
+        );
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone)]
+pub enum PartValue {
+    Text(String),
+    File { path: PathBuf, mime: String },
+}
+
+/// One field of a `<multipart>` block.
+#[derive(Debug, Clone)]
+pub struct FormPart {
+    pub name: String,
+    pub value: PartValue,
+}
+
+/// Parses a `<multipart>` line, `file = @./avatar.png; type=image/png` being a file part
+/// with its path relative to `base_dir`.
+pub fn parse_part(name: &str, value: &str, base_dir: &Path) -> Result<FormPart, String> {
+    let value = match value.strip_prefix('@') {
+        Some(file) => {
+            let (path, options) = file.split_once(';').unwrap_or((file, ""));
+            let mut mime = String::from("application/octet-stream");
+            for option in options
+                .split(';')
+                .map(|o| o.trim())
+                .filter(|o| !o.is_empty())
+            {
+                match option.split_once('=') {
+                    Some(("type", value)) => mime = value.trim().to_string(),
+                    _ => return Err(format!("Unknown file part option `{}`", option)),
+                }
+            }
+            PartValue::File {
+                path: base_dir.join(path.trim()),
+                mime,
+            }
+        }
+        None => PartValue::Text(value.to_string()),
+    };
+
+    Ok(FormPart {
+        name: name.to_string(),
+        value,
+    })
+}
+
+/// Percent encodes everything but unreserved characters, spaces become `+` when `is_form`.
+pub fn percent_encode(value: &str, is_form: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b' ' if is_form => encoded.push('+'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Appends `params` to `url` as a percent encoded query string, after any query it already has
+/// and before any `#fragment`.
+pub fn append_query(url: &str, params: &[(String, String)]) -> String {
+    if params.is_empty() {
+        return url.to_string();
+    }
+    let (url, fragment) = match url.find('#') {
+        Some(index) => url.split_at(index),
+        None => (url, ""),
+    };
+
+    let query = params
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{}={}",
+                percent_encode(key, false),
+                percent_encode(value, false)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("&");
+    let separator = match url.chars().last() {
+        _ if !url.contains('?') => "?",
+        Some('?' | '&') => "",
+        _ => "&",
+    };
+    format!("{}{}{}{}", url, separator, query, fragment)
+}
+
+pub fn encode_form(fields: &[(String, String)]) -> Vec<u8> {
+    fields
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{}={}",
+                percent_encode(key, true),
+                percent_encode(value, true)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("&")
+        .into_bytes()
+}
+
+/// Builds a `multipart/form-data` body, returning it with the matching `Content-Type`.
+pub fn encode_multipart(parts: &[FormPart]) -> Result<(Vec<u8>, String), String> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let boundary = format!("----NetHopBoundary{:x}", nanos);
+
+    let mut body = Vec::new();
+    for part in parts {
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        let name = part.name.replace('"', "%22");
+
+        match &part.value {
+            PartValue::Text(text) => {
+                body.extend_from_slice(
+                    format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name).as_bytes(),
+                );
+                body.extend_from_slice(text.as_bytes());
+            }
+            PartValue::File { path, mime } => {
+                let content = fs::read(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                let file_name = path
+                    .file_name()
+                    .map(|f| f.to_string_lossy().replace('"', "%22"))
+                    .unwrap_or_default();
+
+                body.extend_from_slice(
+                    format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                        name, file_name, mime
+                    )
+                    .as_bytes(),
+                );
+                body.extend_from_slice(&content);
+            }
+        }
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+    Ok((body, format!("multipart/form-data; boundary={}", boundary)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn appends_with_the_right_separator() {
+        let query = params(&[("q", "a b"), ("tag", "x&y")]);
+        assert_eq!(append_query("/search", &query), "/search?q=a%20b&tag=x%26y");
+        assert_eq!(
+            append_query("/search?page=2", &query),
+            "/search?page=2&q=a%20b&tag=x%26y"
+        );
+        assert_eq!(
+            append_query("/search?", &query),
+            "/search?q=a%20b&tag=x%26y"
+        );
+        assert_eq!(append_query("/search", &[]), "/search");
+    }
+
+    #[test]
+    fn keeps_the_fragment_last() {
+        let query = params(&[("q", "1")]);
+        assert_eq!(append_query("/docs#intro", &query), "/docs?q=1#intro");
+        assert_eq!(
+            append_query("/docs?v=2#intro", &query),
+            "/docs?v=2&q=1#intro"
+        );
+    }
+
+    #[test]
+    fn percent_encodes_reserved_and_non_ascii_bytes() {
+        assert_eq!(percent_encode("a-Z_0.~", false), "a-Z_0.~");
+        assert_eq!(
+            percent_encode("a b/c?d=e&f", false),
+            "a%20b%2Fc%3Fd%3De%26f"
+        );
+        assert_eq!(percent_encode("a b+c", true), "a+b%2Bc");
+        assert_eq!(percent_encode("café", false), "caf%C3%A9");
+    }
+
+    #[test]
+    fn encodes_form_fields() {
+        let fields = params(&[("name", "Ada Lovelace"), ("note", "1+1=2")]);
+        assert_eq!(encode_form(&fields), b"name=Ada+Lovelace&note=1%2B1%3D2");
+    }
+
+    #[test]
+    fn parses_file_parts() {
+        let part = parse_part("avatar", "@./me.png; type=image/png", Path::new("data")).unwrap();
+        assert!(matches!(
+            part.value,
+            PartValue::File { path, mime }
+                if path == Path::new("data/./me.png") && mime == "image/png"
+        ));
+        let part = parse_part("notes", "@notes.bin", Path::new("")).unwrap();
+        assert!(matches!(
+            part.value,
+            PartValue::File { mime, .. } if mime == "application/octet-stream"
+        ));
+        assert!(matches!(
+            parse_part("title", "plain", Path::new("")).unwrap().value,
+            PartValue::Text(text) if text == "plain"
+        ));
+        assert_eq!(
+            parse_part("avatar", "@me.png; size=2", Path::new("")).unwrap_err(),
+            "Unknown file part option `size=2`"
+        );
+    }
+
+    #[test]
+    fn encodes_multipart_parts() {
+        let path = std::env::temp_dir().join(format!("nethop-{}-a\"b.txt", std::process::id()));
+        fs::write(&path, "file body").unwrap();
+        let parts = [
+            FormPart {
+                name: String::from("title"),
+                value: PartValue::Text(String::from("Hello")),
+            },
+            FormPart {
+                name: String::from("up\"load"),
+                value: PartValue::File {
+                    path: path.clone(),
+                    mime: String::from("text/plain"),
+                },
+            },
+        ];
+        let encoded = encode_multipart(&parts);
+        fs::remove_file(&path).unwrap();
+
+        let (body, content_type) = encoded.unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/form-data; boundary=")
+            .unwrap();
+        let file_name = path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .replace('"', "%22");
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            format!(
+                "--{boundary}\r\n\
+                Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+                Hello\r\n\
+                --{boundary}\r\n\
+                Content-Disposition: form-data; name=\"up%22load\"; filename=\"{file_name}\"\r\n\
+                Content-Type: text/plain\r\n\r\n\
+                file body\r\n\
+                --{boundary}--\r\n"
+            )
+        );
+        assert!(
+            encode_multipart(&parts)
+                .unwrap_err()
+                .starts_with("Failed to read ")
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use serde_json::{Map, Value, json};
+
+use crate::http::Response;
+
+/// A `<graphql>` block, sent as the standard `{"query", "variables"}` JSON envelope.
+#[derive(Default, Debug, Clone)]
+pub struct GraphQl {
+    pub document: String,
+    /// Raw text of the nested `<variables>` section.
+    pub variables: String,
+}
+
+impl GraphQl {
+    /// Checks the document is there and the variables are a JSON object.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.document.trim().is_empty() {
+            return Err("Empty <graphql> document".to_string());
+        }
+        self.parse_variables().map(|_| ())
+    }
+
+    fn parse_variables(&self) -> Result<Map<String, Value>, String> {
+        if self.variables.trim().is_empty() {
+            return Ok(Map::new());
+        }
+
+        match serde_json::from_str(&self.variables) {
+            Ok(Value::Object(variables)) => Ok(variables),
+            Ok(_) => Err("<variables> must be a JSON object".to_string()),
+            Err(e) => Err(format!("Invalid JSON in <variables>: {}", e)),
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let envelope = json!({
+            "query": self.document.trim(),
+            "variables": self.parse_variables()?,
+        });
+        Ok(envelope.to_string().into_bytes())
+    }
+}
+
+/// Fails with one line per entry of the response's `errors[]`, as GraphQL reports failures
+/// with a 200 status.
+pub fn check_errors(response: &Response) -> Result<(), Vec<String>> {
+    let Ok(json) = serde_json::from_str::<Value>(&response.body) else {
+        return Err(vec!["Response is not JSON".to_string()]);
+    };
+
+    let errors = match json.get("errors") {
+        Some(Value::Array(errors)) if !errors.is_empty() => errors,
+        _ => return Ok(()),
+    };
+
+    Err(errors
+        .iter()
+        .map(|error| {
+            let message = error
+                .get("message")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .unwrap_or_else(|| error.to_string());
+            match error.get("path").and_then(Value::as_array) {
+                Some(path) => {
+                    let path: Vec<String> = path
+                        .iter()
+                        .map(|segment| match segment {
+                            Value::String(key) => key.clone(),
+                            other => other.to_string(),
+                        })
+                        .collect();
+                    format!("error at {}: {}", path.join("."), message)
+                }
+                None => format!("error: {}", message),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graphql(document: &str, variables: &str) -> GraphQl {
+        GraphQl {
+            document: document.to_string(),
+            variables: variables.to_string(),
+        }
+    }
+
+    fn encoded(graphql: &GraphQl) -> Value {
+        serde_json::from_slice(&graphql.encode().unwrap()).unwrap()
+    }
+
+    fn errors(body: &str) -> Result<(), Vec<String>> {
+        let mut response = Response::new("HTTP/1.1", 200, Vec::new());
+        response.body = body.to_string();
+        check_errors(&response)
+    }
+
+    #[test]
+    fn encodes_the_envelope() {
+        assert_eq!(
+            encoded(&graphql("\n  { viewer { login } }\n", "")),
+            json!({"query": "{ viewer { login } }", "variables": {}})
+        );
+        assert_eq!(
+            encoded(&graphql(
+                "query($id: ID!) { node(id: $id) { id } }",
+                "{\n  \"id\": \"42\"\n}\n"
+            )),
+            json!({
+                "query": "query($id: ID!) { node(id: $id) { id } }",
+                "variables": {"id": "42"}
+            })
+        );
+    }
+
+    #[test]
+    fn validates_document_and_variables() {
+        assert!(graphql("{ a }", "").validate().is_ok());
+        assert_eq!(
+            graphql("  \n", "").validate(),
+            Err("Empty <graphql> document".to_string())
+        );
+        assert_eq!(
+            graphql("{ a }", "[1]").validate(),
+            Err("<variables> must be a JSON object".to_string())
+        );
+        assert!(
+            graphql("{ a }", "{\"id\": }")
+                .validate()
+                .unwrap_err()
+                .starts_with("Invalid JSON in <variables>")
+        );
+        assert!(graphql("{ a }", "\"id\"").encode().is_err());
+    }
+
+    #[test]
+    fn reports_each_error() {
+        assert_eq!(errors(r#"{"data": {"a": 1}}"#), Ok(()));
+        assert_eq!(errors(r#"{"data": {"a": 1}, "errors": []}"#), Ok(()));
+        assert_eq!(
+            errors(
+                r#"{"data": null, "errors": [
+                    {"message": "Not found", "path": ["user", 0, "name"]},
+                    {"message": "Rate limited"},
+                    {"extensions": {"code": "X"}}
+                ]}"#
+            ),
+            Err(vec![
+                "error at user.0.name: Not found".to_string(),
+                "error: Rate limited".to_string(),
+                r#"error: {"extensions":{"code":"X"}}"#.to_string(),
+            ])
+        );
+        assert_eq!(
+            errors("<html>"),
+            Err(vec!["Response is not JSON".to_string()])
+        );
+    }
+}
@@ -1,32 +1,32 @@
-use std::error::Error;
+use std::{
+    env::current_dir,
+    path::Path,
+    sync::{Arc, Mutex},
+};
 
 use crate::{
-    http::Request,
-    network::Connection,
-    test_bed::{get_operator, TestCase},
+    auth::Auth,
+    cli::{parse_count, parse_duration},
+    cookies::CookieJar,
+    forms::{append_query, parse_part},
+    graphql::GraphQl,
+    http::{BodySource, Request, StreamTarget, Timings},
+    network::{Connection, Protocol},
+    oauth2::OAuth2,
+    rpc::{RpcCall, parse_params},
+    snapshot::Snapshot,
+    test_bed::{TestCase, get_operator, resolve_schema_path, validate_test_case},
+    variables::substitute,
+    websocket::parse_step,
 };
 
-pub fn clean_script(script: &str) -> Result<String, Box<dyn Error>> {
-    println!("Cleaning script");
-    let mut cleaned_script = String::new();
-    for ch in script.chars() {
-        if ch.is_whitespace() {
-            continue;
-        }
-
-        cleaned_script.push(ch);
-    }
-
-    Ok(cleaned_script)
-}
-
 pub fn fetch_connection_header(script: &str) -> Result<Connection, String> {
     if script.matches("<connect>").count() > 1 {
         return Err("Multiple connect headers found!".to_string());
     }
     let mut lines = script.lines();
 
-    if lines.next() != Some("<connect>") {
+    if lines.next().map(str::trim) != Some("<connect>") {
         return find_connection_header(script);
     }
 
@@ -34,19 +34,52 @@ pub fn fetch_connection_header(script: &str) -> Result<Connection, String> {
         host: String::new(),
         port: 443,
         is_safe: true,
+        protocol: Protocol::default(),
+        auth: None,
         reader: None,
+        h2: Arc::default(),
+        handshake: Timings::default(),
+        cookies: Arc::default(),
     };
 
+    let mut oauth2: Option<OAuth2> = None;
+    let mut is_oauth2 = false;
+
     for line in lines.take_while(|l| l.trim() != "</connect>") {
         let line = line.trim();
         if line.is_empty() {
             continue;
         }
 
+        if is_oauth2 {
+            if line == "</oauth2>" {
+                is_oauth2 = false;
+            } else if let Some(oauth2) = oauth2.as_mut() {
+                parse_oauth2_setting(oauth2, line)?;
+            }
+            continue;
+        } else if line == "<oauth2>" {
+            if oauth2.is_some() {
+                return Err("Multiple oauth2 blocks found!".to_string());
+            }
+            oauth2 = Some(OAuth2::default());
+            is_oauth2 = true;
+            continue;
+        }
+
         if let Some((key, value)) = line.split_once("=") {
             match key.trim() {
                 "host" => conn.host = value.trim().to_string(),
                 "port" => conn.port = value.trim().parse().map_err(|_| "Invalid PORT passed")?,
+                "protocol" => conn.protocol = value.trim().parse()?,
+                "auth" => conn.auth = Some(value.parse()?),
+                "cookie-jar" => {
+                    let mut path =
+                        current_dir().map_err(|_| "Failed to get current working directory")?;
+                    path.push(".nethop");
+                    path.push(value.trim());
+                    conn.cookies = Arc::new(Mutex::new(CookieJar::open(path)?));
+                }
                 _ => return Err(format!("Invalid parameter: {}", key)),
             }
         } else {
@@ -64,14 +97,54 @@ pub fn fetch_connection_header(script: &str) -> Result<Connection, String> {
         return Err("Connection host not specified".into());
     }
 
+    if let Some(oauth2) = oauth2 {
+        if conn.auth.is_some() {
+            return Err("Use either `auth` or an <oauth2> block in <connect>, not both".into());
+        }
+        oauth2.validate()?;
+        conn.auth = Some(Auth::OAuth2(Arc::new(oauth2)));
+    }
+
     Ok(conn)
 }
 
-pub fn find_connection_header(_script: &str) -> Result<Connection, String> {
-    todo!("Search for connection headers from the whole file")
+/// Parses one `key = value` line of an `<oauth2>` block with its `{{NAME}}` variables filled in.
+fn parse_oauth2_setting(oauth2: &mut OAuth2, line: &str) -> Result<(), String> {
+    let (key, value) = line
+        .split_once('=')
+        .ok_or(format!("Expected `key = value` in oauth2, found {}", line))?;
+    let value = substitute(value.trim())?;
+
+    match key.trim() {
+        "token-url" => oauth2.token_url = value,
+        "client-id" => oauth2.client_id = value,
+        "client-secret" => oauth2.client_secret = value,
+        "scope" => {
+            oauth2.scopes = value
+                .split([' ', ','])
+                .filter(|scope| !scope.is_empty())
+                .map(String::from)
+                .collect()
+        }
+        "refresh-token" => oauth2.refresh_token = Some(value),
+        "client-auth" => oauth2.client_auth = value.parse()?,
+        other => return Err(format!("Unknown oauth2 key: {}", other)),
+    }
+    Ok(())
 }
 
-pub fn fetch_requests(script: &str) -> Result<Vec<Request>, String> {
+/// Looks for the `<connect>` block further down, after comments or blank lines.
+pub fn find_connection_header(script: &str) -> Result<Connection, String> {
+    let start = script
+        .lines()
+        .position(|line| line.trim() == "<connect>")
+        .ok_or("No <connect> block found")?;
+    let rest: Vec<&str> = script.lines().skip(start).collect();
+    fetch_connection_header(&rest.join("\n"))
+}
+
+/// Parses every `<query>` block, resolving file references against `base_dir`.
+pub fn fetch_requests(script: &str, base_dir: &Path) -> Result<Vec<Request>, String> {
     let lines = script.lines();
     let estimated_size = script.matches("<query>").count();
     let mut requests: Vec<Request> = Vec::with_capacity(estimated_size);
@@ -80,7 +153,16 @@ pub fn fetch_requests(script: &str) -> Result<Vec<Request>, String> {
 
     let mut is_query = false;
     let mut is_body = false;
+    let mut is_form = false;
+    let mut is_params = false;
+    let mut params: Vec<(String, String)> = Vec::new();
+    let mut is_multipart = false;
     let mut is_test_case = false;
+    let mut is_websocket = false;
+    let mut is_graphql = false;
+    let mut is_rpc = false;
+    let mut next_rpc_id = 1;
+    let mut is_variables = false;
 
     for line in lines {
         let trimmed = line.trim();
@@ -90,15 +172,90 @@ pub fn fetch_requests(script: &str) -> Result<Vec<Request>, String> {
 
         if trimmed == "<query>" {
             current_request = Request {
-                url: String::new(),
                 method: String::from("GET"),
-                body: String::new(),
-                content_type: String::new(),
-                test_cases: Vec::new(),
+                ..Default::default()
             };
+            params.clear();
             is_query = true;
             continue;
         } else if trimmed == "</query>" {
+            current_request.url = append_query(&current_request.url, &params);
+            let payloads = [
+                !current_request.body.is_empty(),
+                !current_request.form.is_empty(),
+                !current_request.multipart.is_empty(),
+                current_request.body_source.is_some(),
+                current_request.graphql.is_some(),
+                !current_request.rpc.is_empty(),
+            ];
+            if payloads.iter().filter(|&&p| p).count() > 1 {
+                return Err(format!(
+                    "Query {} can only have one of <body>, <form>, <multipart>, <graphql>, <rpc> or `body = @file`",
+                    current_request.label()
+                ));
+            }
+            if (current_request.graphql.is_some() || !current_request.rpc.is_empty())
+                && current_request.method == "GET"
+            {
+                // Only the POST envelopes of GraphQL and JSON-RPC are supported
+                current_request.method = String::from("POST");
+            }
+            if let Some(graphql) = &current_request.graphql {
+                graphql
+                    .validate()
+                    .map_err(|e| format!("Query {}: {}", current_request.label(), e))?;
+            }
+            for dependency in &current_request.depends_on {
+                if !requests.iter().any(|r| &r.name == dependency) {
+                    return Err(format!(
+                        "Query {} depends on `{}` which is not declared before it",
+                        current_request.label(),
+                        dependency
+                    ));
+                }
+            }
+            if !current_request.websocket.is_empty()
+                && (current_request.method != "GET"
+                    || payloads.contains(&true)
+                    || current_request.stream.is_some())
+            {
+                return Err(format!(
+                    "WebSocket query {} must be a GET without a body or stream",
+                    current_request.label()
+                ));
+            }
+            if current_request.stream.is_some()
+                && (current_request.snapshot.is_some()
+                    || current_request.test_cases.iter().any(|c| c.key == "body"))
+            {
+                return Err(format!(
+                    "Query {} streams its body, only status, header and timing assertions are allowed",
+                    current_request.label()
+                ));
+            }
+            let label = current_request.label();
+            if let Some(snapshot) = current_request.snapshot.as_mut() {
+                let is_taken = |snapshot: &Snapshot| {
+                    requests
+                        .iter()
+                        .filter_map(|r| r.snapshot.as_ref())
+                        .any(|other| other.file_name() == snapshot.file_name())
+                };
+                if snapshot.name.is_empty() {
+                    // Unnamed queries of the same endpoint get a numbered snapshot each
+                    snapshot.name = label.clone();
+                    let mut number = 2;
+                    while is_taken(snapshot) {
+                        snapshot.name = format!("{} {}", label, number);
+                        number += 1;
+                    }
+                } else if is_taken(snapshot) {
+                    return Err(format!(
+                        "Snapshot `{}` of query {} is already used by an earlier query",
+                        snapshot.name, label
+                    ));
+                }
+            }
             requests.push(std::mem::take(&mut current_request));
             is_query = false;
             continue;
@@ -112,22 +269,114 @@ pub fn fetch_requests(script: &str) -> Result<Vec<Request>, String> {
                 current_request.body.push('\n');
             }
             continue;
+        } else if is_form {
+            if trimmed == "</form>" {
+                is_form = false;
+            } else {
+                let (key, value) = trimmed
+                    .split_once('=')
+                    .ok_or(format!("Expected `key = value` in form, found {}", trimmed))?;
+                current_request
+                    .form
+                    .push((key.trim().to_string(), value.trim().to_string()));
+            }
+            continue;
+        } else if is_params {
+            if trimmed == "</params>" {
+                is_params = false;
+            } else {
+                params.extend(parse_param(trimmed)?);
+            }
+            continue;
+        } else if is_multipart {
+            if trimmed == "</multipart>" {
+                is_multipart = false;
+            } else {
+                let (key, value) = trimmed.split_once('=').ok_or(format!(
+                    "Expected `key = value` in multipart, found {}",
+                    trimmed
+                ))?;
+                current_request
+                    .multipart
+                    .push(parse_part(key.trim(), value.trim(), base_dir)?);
+            }
+            continue;
+        } else if is_rpc {
+            if trimmed == "</rpc>" {
+                is_rpc = false;
+                continue;
+            }
+
+            let (key, value) = trimmed
+                .split_once('=')
+                .ok_or(format!("Expected `key = value` in rpc, found {}", trimmed))?;
+            match key.trim() {
+                "method" => {
+                    current_request.rpc.push(RpcCall {
+                        id: next_rpc_id,
+                        method: value.trim().to_string(),
+                        params: None,
+                    });
+                    next_rpc_id += 1;
+                }
+                "params" => {
+                    current_request
+                        .rpc
+                        .last_mut()
+                        .ok_or("RPC params given before any method")?
+                        .params = Some(parse_params(value.trim())?)
+                }
+                other => return Err(format!("Unknown rpc key: {}", other)),
+            }
+            continue;
+        } else if is_variables {
+            let graphql = current_request.graphql.get_or_insert_default();
+            if trimmed == "</variables>" {
+                is_variables = false;
+            } else {
+                graphql.variables.push_str(line);
+                graphql.variables.push('\n');
+            }
+            continue;
+        } else if is_graphql {
+            let graphql = current_request.graphql.get_or_insert_default();
+            if trimmed == "</graphql>" {
+                is_graphql = false;
+            } else if trimmed == "<variables>" {
+                is_variables = true;
+            } else {
+                graphql.document.push_str(line);
+                graphql.document.push('\n');
+            }
+            continue;
+        } else if is_websocket {
+            if trimmed == "</websocket>" {
+                is_websocket = false;
+            } else {
+                current_request
+                    .websocket
+                    .push(parse_step(trimmed, base_dir)?);
+            }
+            continue;
         } else if is_test_case {
             if trimmed == "</assert>" {
                 is_test_case = false;
             } else {
-                let expression: Vec<&str> = trimmed.splitn(3, " ").collect();
-                if expression.len() != 3 {
-                    return Err(format!(
-                        "Invalid expression in assertion, {}, {}",
-                        trimmed,
-                        expression.len()
-                    ));
-                }
+                let (key, rest) = trimmed
+                    .split_once(' ')
+                    .ok_or(format!("Invalid expression in assertion, {}", trimmed))?;
+                let (count, rest) = match rest.strip_prefix("count ") {
+                    Some(rest) => (true, rest),
+                    None => (false, rest),
+                };
+                let (operator, value) = rest.split_once(' ').unwrap_or((rest, ""));
 
-                current_test_case.key = String::from(expression[0]);
-                current_test_case.operation = get_operator(expression[1])?;
-                current_test_case.value = String::from(expression[2]);
+                current_test_case.key = String::from(key);
+                current_test_case.count = count;
+                current_test_case.operation = get_operator(operator)?;
+                current_test_case.value = String::from(value);
+                resolve_schema_path(&mut current_test_case, base_dir);
+                validate_test_case(&current_test_case)?;
                 current_request
                     .test_cases
                     .push(std::mem::take(&mut current_test_case));
@@ -140,6 +389,37 @@ pub fn fetch_requests(script: &str) -> Result<Vec<Request>, String> {
                 continue;
             }
 
+            if trimmed == "<form>" {
+                is_form = true;
+                continue;
+            }
+
+            if trimmed == "<multipart>" {
+                is_multipart = true;
+                continue;
+            }
+
+            if trimmed == "<params>" {
+                is_params = true;
+                continue;
+            }
+
+            if trimmed == "<graphql>" {
+                current_request.graphql = Some(GraphQl::default());
+                is_graphql = true;
+                continue;
+            }
+
+            if trimmed == "<rpc>" {
+                is_rpc = true;
+                continue;
+            }
+
+            if trimmed == "<websocket>" {
+                is_websocket = true;
+                continue;
+            }
+
             if trimmed == "<assert>" {
                 is_test_case = true;
                 continue;
@@ -148,8 +428,42 @@ pub fn fetch_requests(script: &str) -> Result<Vec<Request>, String> {
             if let Some((key, value)) = line.split_once('=') {
                 match key.trim() {
                     "url" => current_request.url = value.trim().to_string(),
+                    "body" => {
+                        current_request.body_source = Some(parse_body_source(value, base_dir)?)
+                    }
+                    "transfer-encoding" => match value.trim() {
+                        "chunked" => current_request.chunked = true,
+                        other => return Err(format!("Unsupported transfer-encoding: {}", other)),
+                    },
+                    "expect" => match value.trim() {
+                        "100-continue" => current_request.expect_continue = true,
+                        other => return Err(format!("Unsupported expect: {}", other)),
+                    },
+                    "stream" => {
+                        current_request.stream = Some(parse_stream_target(value, base_dir)?)
+                    }
+                    "events" => {
+                        current_request.events.max_events =
+                            Some(parse_count("events", value.trim())?)
+                    }
+                    "events-timeout" => {
+                        current_request.events.timeout = Some(parse_duration(value.trim())?)
+                    }
                     "method" => current_request.method = value.trim().to_uppercase(),
                     "content-type" => current_request.content_type = value.trim().to_lowercase(),
+                    "auth" => current_request.auth = Some(value.parse()?),
+                    "name" => current_request.name = value.trim().to_string(),
+                    "depends" => current_request.depends_on = split_list(value),
+                    "snapshot" => {
+                        current_request.snapshot.get_or_insert_default().name =
+                            value.trim().to_string()
+                    }
+                    "snapshot-headers" => {
+                        current_request.snapshot.get_or_insert_default().headers = split_list(value)
+                    }
+                    "snapshot-ignore" => {
+                        current_request.snapshot.get_or_insert_default().ignore = split_list(value)
+                    }
                     _ => return Err(format!("Unknown key: {}", key)),
                 }
             }
@@ -158,3 +472,297 @@ pub fn fetch_requests(script: &str) -> Result<Vec<Request>, String> {
 
     Ok(requests)
 }
+
+/// Parses one `<params>` line with its `{{NAME}}` variables filled in. `key = [a, b]` stands for
+/// the key repeated with each value.
+fn parse_param(line: &str) -> Result<Vec<(String, String)>, String> {
+    let (key, value) = line
+        .split_once('=')
+        .ok_or(format!("Expected `key = value` in params, found {}", line))?;
+    let key = substitute(key.trim())?;
+    let value = value.trim();
+
+    let values = match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        Some(items) if items.trim().is_empty() => Vec::new(),
+        Some(items) => split_items(items)?,
+        None => vec![value.to_string()],
+    };
+
+    values
+        .into_iter()
+        .map(|value| Ok((key.clone(), substitute(&value)?)))
+        .collect()
+}
+
+/// Splits `a, "b, c", "say \"hi\""` on the commas outside quotes, unquoting each item.
+fn split_items(items: &str) -> Result<Vec<String>, String> {
+    let mut values = Vec::new();
+    let mut rest = items.trim_start();
+
+    loop {
+        let (value, after) = match rest.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let end = loop {
+                    match chars.next() {
+                        Some((index, '"')) => break index + 1,
+                        Some((_, '\\')) => value.extend(chars.next().map(|(_, ch)| ch)),
+                        Some((_, ch)) => value.push(ch),
+                        None => return Err(format!("Unclosed quote in [{}]", items)),
+                    }
+                };
+                let after = quoted[end..].trim_start();
+                if !after.is_empty() && !after.starts_with(',') {
+                    return Err(format!("Expected `,` after \"{}\" in [{}]", value, items));
+                }
+                (value, after)
+            }
+            None => {
+                let end = rest.find(',').unwrap_or(rest.len());
+                (rest[..end].trim().to_string(), &rest[end..])
+            }
+        };
+
+        values.push(value);
+        match after.strip_prefix(',') {
+            Some(after) => rest = after.trim_start(),
+            None => return Ok(values),
+        }
+    }
+}
+
+/// Parses `@fixtures/order.json`, optionally followed by `; substitute`, or `generate 2GB`.
+fn parse_body_source(value: &str, base_dir: &Path) -> Result<BodySource, String> {
+    let value = value.trim();
+    if let Some(size) = value.strip_prefix("generate ") {
+        return Ok(BodySource::Generated {
+            len: parse_size(size.trim())?,
+        });
+    }
+
+    let file = value.strip_prefix('@').ok_or(format!(
+        "Expected `body = @path/to/file` or `body = generate <size>`, found {}",
+        value
+    ))?;
+    let (path, options) = file.split_once(';').unwrap_or((file, ""));
+
+    let mut substitute = false;
+    for option in options
+        .split(';')
+        .map(|o| o.trim())
+        .filter(|o| !o.is_empty())
+    {
+        match option {
+            "substitute" => substitute = true,
+            _ => return Err(format!("Unknown body file option `{}`", option)),
+        }
+    }
+
+    Ok(BodySource::File {
+        path: base_dir.join(path.trim()),
+        substitute,
+    })
+}
+
+/// Parses `pager` or `@path/to/file`, the file being created or overwritten.
+fn parse_stream_target(value: &str, base_dir: &Path) -> Result<StreamTarget, String> {
+    match value.trim() {
+        "pager" => Ok(StreamTarget::Pager),
+        value => value
+            .strip_prefix('@')
+            .map(|path| StreamTarget::File(base_dir.join(path.trim())))
+            .ok_or(format!(
+                "Expected `stream = pager` or `stream = @path/to/file`, found {}",
+                value
+            )),
+    }
+}
+
+/// Parses sizes like `512`, `64KB`, `10MB` or `2GB`, units being powers of 1024.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid size: {}", value);
+    let split = value
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number.parse::<u64>().map_err(|_| invalid())?;
+
+    let multiplier: u64 = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" => 1 << 10,
+        "MB" => 1 << 20,
+        "GB" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    number.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(line: &str) -> Vec<String> {
+        parse_param(line)
+            .unwrap()
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    #[test]
+    fn param_lists_repeat_the_key() {
+        assert_eq!(
+            parse_param("id = [1, 2]").unwrap(),
+            [
+                ("id".to_string(), "1".to_string()),
+                ("id".to_string(), "2".to_string())
+            ]
+        );
+        assert_eq!(values(r#"tag = ["a", b]"#), ["a", "b"]);
+        assert!(values("ids = []").is_empty());
+    }
+
+    #[test]
+    fn param_lists_split_outside_quotes() {
+        assert_eq!(values(r#"tags = ["a,b", c]"#), ["a,b", "c"]);
+        assert_eq!(
+            values(r#"ids = [1, 2, "three four", " padded "]"#),
+            ["1", "2", "three four", " padded "]
+        );
+        assert_eq!(values(r#"q = ["say \"hi\""]"#), [r#"say "hi""#]);
+        assert!(values("ids = []").is_empty());
+    }
+
+    #[test]
+    fn param_lists_reject_broken_quotes() {
+        assert!(parse_param(r#"tags = ["a, b]"#).is_err());
+        assert!(parse_param(r#"tags = ["a" b]"#).is_err());
+    }
+
+    #[test]
+    fn plain_param_keeps_commas() {
+        assert_eq!(values("range = 1,5"), ["1,5"]);
+    }
+
+    #[test]
+    fn numbers_rpc_calls_across_the_script() {
+        let script = "
+<query>
+url = http://localhost/rpc
+<rpc>
+method = ping
+method = sum
+params = [1, 2]
+</rpc>
+</query>
+<query>
+url = http://localhost/rpc
+<rpc>
+method = echo
+</rpc>
+</query>";
+        let requests = fetch_requests(script, Path::new(".")).unwrap();
+        let ids: Vec<Vec<(u64, &str)>> = requests
+            .iter()
+            .map(|r| r.rpc.iter().map(|c| (c.id, c.method.as_str())).collect())
+            .collect();
+        assert_eq!(ids, [vec![(1, "ping"), (2, "sum")], vec![(3, "echo")]]);
+        assert_eq!(requests[0].method, "POST");
+        assert!(
+            fetch_requests(
+                "<query>\nurl = http://x\n<rpc>\nparams = [1]\n</rpc>\n</query>",
+                Path::new(".")
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn parses_sizes_in_binary_units() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512B"), Ok(512));
+        assert_eq!(parse_size("64kb"), Ok(64 << 10));
+        assert_eq!(parse_size("10 MB"), Ok(10 << 20));
+        assert_eq!(parse_size("2GB"), Ok(2 << 30));
+        assert_eq!(parse_size("0KB"), Ok(0));
+        for size in ["", "GB", "2TB", "1.5MB", "-1", "99999999999999999999"] {
+            assert_eq!(parse_size(size), Err(format!("Invalid size: {}", size)));
+        }
+        // Fits a u64 but overflows once multiplied out
+        assert!(parse_size("17179869184GB").is_err());
+        assert_eq!(parse_size("17179869183GB"), Ok(17179869183 << 30));
+
+        let base = Path::new("/scripts");
+        assert!(matches!(
+            parse_body_source("generate 2GB", base),
+            Ok(BodySource::Generated { len }) if len == 2 << 30
+        ));
+        assert!(parse_body_source("generate lots", base).is_err());
+    }
+
+    #[test]
+    fn parses_body_sources() {
+        let base = Path::new("/scripts");
+        assert!(matches!(
+            parse_body_source(" @data/user.json ", base),
+            Ok(BodySource::File { path, substitute: false }) if path == base.join("data/user.json")
+        ));
+        assert!(matches!(
+            parse_body_source("@user.json; substitute", base),
+            Ok(BodySource::File { path, substitute: true }) if path == base.join("user.json")
+        ));
+        assert_eq!(
+            parse_body_source("@user.json; gzip", base).err(),
+            Some("Unknown body file option `gzip`".to_string())
+        );
+        assert!(
+            parse_body_source("user.json", base)
+                .err()
+                .unwrap()
+                .starts_with("Expected `body = @path/to/file`")
+        );
+    }
+
+    #[test]
+    fn parses_stream_targets() {
+        let base = Path::new("/scripts");
+        assert!(matches!(
+            parse_stream_target(" pager ", base),
+            Ok(StreamTarget::Pager)
+        ));
+        assert!(matches!(
+            parse_stream_target("@out/dump.bin", base),
+            Ok(StreamTarget::File(path)) if path == base.join("out/dump.bin")
+        ));
+        for target in ["less", "out.bin", ""] {
+            assert_eq!(
+                parse_stream_target(target, base).err(),
+                Some(format!(
+                    "Expected `stream = pager` or `stream = @path/to/file`, found {}",
+                    target
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn finds_the_connect_block_below_other_lines() {
+        let conn =
+            fetch_connection_header("\n  <connect>\n  host = example.com\n</connect>\n").unwrap();
+        assert_eq!(conn.host, "example.com");
+        assert_eq!(conn.port, 443);
+        assert_eq!(
+            fetch_connection_header("<query>\nurl = /\n</query>").err(),
+            Some("No <connect> block found".to_string())
+        );
+    }
+}
@@ -1,8 +1,78 @@
-use std::io::{BufRead, BufReader, Read};
+use std::{
+    fmt, fs,
+    io::{self, BufRead, ErrorKind, Read, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use serde_json::Value;
 
-use crate::{network::Stream, test_bed::TestCase};
+use crate::{
+    auth::Auth,
+    forms::{FormPart, encode_form, encode_multipart},
+    graphql::GraphQl,
+    rpc::{RpcCall, encode as encode_rpc},
+    snapshot::Snapshot,
+    test_bed::TestCase,
+    variables::substitute as substitute_variables,
+    websocket::Step,
+};
+
+/// A body given with the `body` key, produced when the request is sent.
+#[derive(Debug, Clone)]
+pub enum BodySource {
+    /// `body = @path`, `substitute` replacing `{{NAME}}` variables which means buffering it.
+    File { path: PathBuf, substitute: bool },
+    /// `body = generate 2GB`, filler bytes for upload tests that should not touch the disk.
+    Generated { len: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub enum StreamTarget {
+    Pager,
+    File(PathBuf),
+}
+
+/// When to stop reading a `text/event-stream` response, which otherwise runs until the server
+/// closes it.
+#[derive(Default, Debug, Clone)]
+pub struct EventLimit {
+    pub max_events: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// Streamed from disk straight to the socket.
+    File {
+        path: PathBuf,
+        len: u64,
+    },
+    Generated {
+        len: u64,
+    },
+}
+
+impl Body {
+    pub fn len(&self) -> u64 {
+        match self {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File { len, .. } | Body::Generated { len } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        Ok(match self {
+            Body::Bytes(bytes) => Box::new(bytes.as_slice()),
+            Body::File { path, .. } => Box::new(fs::File::open(path)?),
+            Body::Generated { len } => Box::new(io::repeat(b'0').take(*len)),
+        })
+    }
+}
 
 #[derive(Default, Debug)]
 pub struct Request {
@@ -11,120 +81,594 @@ pub struct Request {
     pub body: String,
     pub content_type: String,
     pub test_cases: Vec<TestCase>,
+    pub name: String,
+    pub depends_on: Vec<String>,
+    pub snapshot: Option<Snapshot>,
+    /// Fields of a `<form>` block, sent url encoded.
+    pub form: Vec<(String, String)>,
+    /// Parts of a `<multipart>` block.
+    pub multipart: Vec<FormPart>,
+    pub body_source: Option<BodySource>,
+    /// Send the body with `Transfer-Encoding: chunked` instead of a `Content-Length`.
+    pub chunked: bool,
+    /// Overrides the `auth` of `<connect>`.
+    pub auth: Option<Auth>,
+    /// Send `Expect: 100-continue` and hold the body until the server agrees to take it.
+    pub expect_continue: bool,
+    /// Where to send the body as it arrives instead of buffering it.
+    pub stream: Option<StreamTarget>,
+    pub events: EventLimit,
+    pub graphql: Option<GraphQl>,
+    /// Calls of the `<rpc>` blocks, batched into one request when there are several.
+    pub rpc: Vec<RpcCall>,
+    /// Steps of a `<websocket>` block, played after upgrading the connection.
+    pub websocket: Vec<Step>,
+}
+
+impl Request {
+    pub fn label(&self) -> String {
+        if self.name.is_empty() {
+            format!("[{}: {}]", self.method, self.url)
+        } else {
+            format!("[{}]", self.name)
+        }
+    }
+
+    /// Headers describing the request and its `body`, in the order they are sent. Content
+    /// headers only go out with a body, and an empty `Content-Length` only for methods defined
+    /// to carry one. `Transfer-Encoding` and `Expect` are for HTTP/1.1 alone.
+    pub fn headers(
+        &self,
+        body: &Body,
+        content_type: &str,
+        cookie: Option<&str>,
+    ) -> Vec<(String, String)> {
+        let has_body = !body.is_empty();
+        let mut headers = vec![(String::from("User-Agent"), String::from("NetHop/0.0"))];
+        if has_body && !content_type.is_empty() {
+            headers.push((String::from("Content-Type"), content_type.to_string()));
+        }
+        if has_body && self.chunked {
+            headers.push((String::from("Transfer-Encoding"), String::from("chunked")));
+        } else if has_body || matches!(self.method.as_str(), "POST" | "PUT" | "PATCH") {
+            headers.push((String::from("Content-Length"), body.len().to_string()));
+        }
+        headers.push((String::from("Accept"), String::from("application/json")));
+        headers.push((String::from("Accept-Encoding"), String::from("identity")));
+        if has_body && self.expect_continue {
+            headers.push((String::from("Expect"), String::from("100-continue")));
+        }
+        if let Some(cookie) = cookie {
+            headers.push((String::from("Cookie"), cookie.to_string()));
+        }
+        headers
+    }
+
+    /// The request line and headers of the HTTP/1.1 request, through the blank line.
+    pub fn encode_head(&self, host: &str, headers: &[(String, String)]) -> String {
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: keep-alive\r\n",
+            self.method, self.url, host
+        );
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        head
+    }
+
+    /// The body to send together with its `Content-Type`.
+    pub fn encode_body(&self) -> Result<(Body, String), String> {
+        if !self.form.is_empty() {
+            Ok((
+                Body::Bytes(encode_form(&self.form)),
+                String::from("application/x-www-form-urlencoded"),
+            ))
+        } else if let Some(graphql) = &self.graphql {
+            let content_type = if self.content_type.is_empty() {
+                String::from("application/json")
+            } else {
+                self.content_type.clone()
+            };
+            Ok((Body::Bytes(graphql.encode()?), content_type))
+        } else if !self.rpc.is_empty() {
+            let content_type = if self.content_type.is_empty() {
+                String::from("application/json")
+            } else {
+                self.content_type.clone()
+            };
+            Ok((Body::Bytes(encode_rpc(&self.rpc)), content_type))
+        } else if !self.multipart.is_empty() {
+            let (bytes, content_type) = encode_multipart(&self.multipart)?;
+            Ok((Body::Bytes(bytes), content_type))
+        } else if let Some(BodySource::File { path, substitute }) = &self.body_source {
+            let content_type = if self.content_type.is_empty() {
+                guess_mime(path).to_string()
+            } else {
+                self.content_type.clone()
+            };
+            let read_error = |e: io::Error| format!("Failed to read {}: {}", path.display(), e);
+
+            let body = if *substitute {
+                let template = fs::read_to_string(path).map_err(read_error)?;
+                Body::Bytes(substitute_variables(&template)?.into_bytes())
+            } else {
+                let len = fs::metadata(path).map_err(read_error)?.len();
+                Body::File {
+                    path: path.clone(),
+                    len,
+                }
+            };
+            Ok((body, content_type))
+        } else if let Some(BodySource::Generated { len }) = &self.body_source {
+            let content_type = if self.content_type.is_empty() {
+                String::from("application/octet-stream")
+            } else {
+                self.content_type.clone()
+            };
+            Ok((Body::Generated { len: *len }, content_type))
+        } else {
+            let content_type = if self.content_type.is_empty() {
+                String::from("application/json")
+            } else {
+                self.content_type.clone()
+            };
+            Ok((Body::Bytes(self.body.as_bytes().to_vec()), content_type))
+        }
+    }
+}
+
+fn guess_mime(path: &std::path::Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("txt") => "text/plain",
+        Some("html") => "text/html",
+        Some("csv") => "text/csv",
+        _ => "application/octet-stream",
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Timings {
+    pub dns: Duration,
+    pub connect: Duration,
+    pub tls: Duration,
+    pub ttfb: Duration,
+    pub download: Duration,
+}
+
+impl Timings {
+    pub fn total(&self) -> Duration {
+        self.dns + self.connect + self.tls + self.ttfb + self.download
+    }
+
+    /// Looks up a phase by the name used in assertions, `time` being the total.
+    pub fn get(&self, phase: &str) -> Option<Duration> {
+        match phase {
+            "time" => Some(self.total()),
+            "dns" => Some(self.dns),
+            "connect" => Some(self.connect),
+            "tls" => Some(self.tls),
+            "ttfb" => Some(self.ttfb),
+            "download" => Some(self.download),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "dns {:.2}ms | connect {:.2}ms | tls {:.2}ms | ttfb {:.2}ms | download {:.2}ms | total {:.2}ms",
+            ms(self.dns),
+            ms(self.connect),
+            ms(self.tls),
+            ms(self.ttfb),
+            ms(self.download),
+            ms(self.total())
+        )
+    }
 }
 
 pub struct Response {
+    /// Protocol the response came over, `HTTP/1.1` or `HTTP/2`.
+    pub version: String,
     pub status: u16,
-    pub headers: String,
+    /// Header lines in the order received, repeated headers kept as separate entries.
+    pub headers: Vec<(String, String)>,
+    /// Fields sent after the body, by a chunked HTTP/1.1 body or a trailing HTTP/2 HEADERS frame.
+    pub trailers: Vec<(String, String)>,
     pub body: String,
+    pub timings: Timings,
+    /// Number of body bytes sent to a stream target instead of being kept in `body`.
+    pub streamed: Option<u64>,
+    /// Events of a `text/event-stream` body, in the order received.
+    pub events: Vec<SseEvent>,
+    /// Results of the query's RPC calls, matched back to them by id.
+    pub rpc_results: Vec<Value>,
 }
 
 impl Response {
+    /// A response with only its head, the body being filled in as it is read.
+    pub fn new(version: &str, status: u16, headers: Vec<(String, String)>) -> Self {
+        Response {
+            version: version.to_string(),
+            status,
+            headers,
+            trailers: Vec::new(),
+            body: String::new(),
+            timings: Timings::default(),
+            streamed: None,
+            events: Vec::new(),
+            rpc_results: Vec::new(),
+        }
+    }
+
     pub fn get_header(&self, k: &str) -> Option<&str> {
-        for line in self.headers.split("\r\n") {
-            if let Some((key, value)) = line.split_once(": ")
-                && key.to_lowercase() == k.to_lowercase()
-            {
-                return Some(value);
-            }
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(k))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_event_stream(&self) -> bool {
+        self.get_header("content-type")
+            .and_then(|value| value.split(';').next())
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/event-stream"))
+    }
+
+    /// Whether the server leaves the connection open for the next request.
+    pub fn keeps_connection(&self) -> bool {
+        let connection = self
+            .get_header("connection")
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if self.version == "HTTP/1.0" {
+            connection.contains("keep-alive")
+        } else {
+            !connection.contains("close")
         }
+    }
 
-        None
+    /// Every value of a header, in the order the server sent them.
+    pub fn get_headers(&self, k: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(k))
+            .map(|(_, value)| value.as_str())
+            .collect()
     }
 }
 
-pub fn read_body(stream: &mut Stream) -> Result<String, String> {
-    let mut reader = BufReader::new(stream);
-    let mut content_length = 0;
-    let mut headers = String::new();
-    let mut is_chunked = false;
+#[derive(Default, Debug, Clone)]
+pub struct SseEvent {
+    /// The `event:` field, `message` when the server sent none.
+    pub event: String,
+    /// Every `data:` line of the event joined by newlines.
+    pub data: String,
+    pub id: Option<String>,
+    pub retry: Option<u64>,
+}
 
-    loop {
-        let mut line = String::new();
-        reader
-            .read_line(&mut line)
-            .map_err(|_| "Failed to read stream")?;
+impl fmt::Display for SseEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.event)?;
+        if let Some(id) = &self.id {
+            write!(f, " id={}", id)?;
+        }
+        if let Some(retry) = self.retry {
+            write!(f, " retry={}ms", retry)?;
+        }
+        write!(f, ": {}", self.data.replace('\n', "\n    "))
+    }
+}
+
+/// Builds events out of `text/event-stream` lines fed one at a time.
+#[derive(Default)]
+pub struct SseParser {
+    pending: SseEvent,
+    has_data: bool,
+}
 
-        if line == "\r\n" || line.is_empty() {
-            break;
+impl SseParser {
+    /// Takes one line, returning the event it completes if it is the blank line ending one.
+    pub fn feed(&mut self, line: &str) -> Option<SseEvent> {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if line.is_empty() {
+            let mut event = std::mem::take(&mut self.pending);
+            // Events without data are dropped, as browsers do
+            if !std::mem::take(&mut self.has_data) {
+                return None;
+            }
+            if event.event.is_empty() {
+                event.event = String::from("message");
+            }
+            return Some(event);
         }
 
-        let cleaned_line = line.to_lowercase();
-        if cleaned_line.starts_with("content-length:") {
-            content_length = line
-                .split_once(':')
-                .map(|(_, val)| val.trim().parse::<usize>().unwrap_or(0))
-                .unwrap_or(0);
-        } else if cleaned_line.starts_with("transfer-encoding: chunked") {
-            is_chunked = true;
+        if line.starts_with(':') {
+            return None;
         }
 
-        headers.push_str(&line);
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.pending.event = value.to_string(),
+            "data" => {
+                if self.has_data {
+                    self.pending.data.push('\n');
+                }
+                self.pending.data.push_str(value);
+                self.has_data = true;
+            }
+            "id" if !value.contains('\0') => self.pending.id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(retry) = value.parse() {
+                    self.pending.retry = Some(retry);
+                }
+            }
+            _ => {}
+        }
+        None
     }
+}
 
-    let mut body = Vec::new();
+/// How the length of a response body is determined, as announced by its headers.
+pub struct BodyFraming {
+    /// Unset when neither a length nor chunking is announced, the body running until the
+    /// server closes the connection.
+    pub content_length: Option<usize>,
+    pub is_chunked: bool,
+}
+
+impl BodyFraming {
+    /// Framing of a response without a body.
+    pub fn empty() -> Self {
+        BodyFraming {
+            content_length: Some(0),
+            is_chunked: false,
+        }
+    }
+
+    pub fn is_close_delimited(&self) -> bool {
+        !self.is_chunked && self.content_length.is_none()
+    }
+}
+
+/// Whether a body follows the head. Responses to HEAD and 1xx, 204 and 304 responses have none
+/// whatever their headers announce (RFC 9112, section 6.3).
+pub fn response_has_body(method: &str, status: u16) -> bool {
+    !method.eq_ignore_ascii_case("HEAD") && !matches!(status, 100..=199 | 204 | 304)
+}
+
+/// Reads the status line and headers, `started` being when the request began to be written.
+/// Interim 1xx responses are skipped, except `101 Switching Protocols` which ends the exchange.
+pub fn read_head(
+    reader: &mut impl BufRead,
+    started: Instant,
+    timings: &mut Timings,
+) -> Result<(String, BodyFraming), String> {
+    reader.fill_buf().map_err(|_| "Failed to read stream")?;
+    timings.ttfb = started.elapsed();
+
+    loop {
+        let mut framing = BodyFraming {
+            content_length: None,
+            is_chunked: false,
+        };
+        let mut headers = String::new();
 
-    if is_chunked {
         loop {
-            let mut size_line = String::new();
+            let mut line = String::new();
             reader
-                .read_line(&mut size_line)
-                .map_err(|e| e.to_string())?;
-
-            let chunk_size =
-                usize::from_str_radix(size_line.trim(), 16).map_err(|e| e.to_string())?;
+                .read_line(&mut line)
+                .map_err(|_| "Failed to read stream")?;
 
-            if chunk_size == 0 {
+            if line == "\r\n" || line.is_empty() {
                 break;
             }
 
-            let mut chunk_data = vec![0u8; chunk_size];
-            reader
-                .read_exact(&mut chunk_data)
-                .map_err(|e| e.to_string())?;
+            if let Some((key, value)) = line.split_once(':') {
+                let key = key.trim();
+                if key.eq_ignore_ascii_case("content-length") {
+                    framing.content_length = Some(value.trim().parse::<usize>().unwrap_or(0));
+                } else if key.eq_ignore_ascii_case("transfer-encoding")
+                    && value.to_lowercase().contains("chunked")
+                {
+                    framing.is_chunked = true;
+                }
+            }
+
+            headers.push_str(&line);
+        }
+
+        let status = headers.split_whitespace().nth(1).unwrap_or_default();
+        if !(status.len() == 3 && status.starts_with('1')) || status == "101" {
+            return Ok((headers, framing));
+        }
+    }
+}
+
+/// Decodes a body off the stream as it is read, stopping where its framing says it ends.
+pub struct BodyReader<'a, R: BufRead> {
+    reader: &'a mut R,
+    is_chunked: bool,
+    until_close: bool,
+    /// Bytes left in the current chunk, or in the whole body when not chunked.
+    remaining: u64,
+    in_chunk: bool,
+    done: bool,
+    trailers: Vec<(String, String)>,
+}
+
+impl<'a, R: BufRead> BodyReader<'a, R> {
+    pub fn new(reader: &'a mut R, framing: &BodyFraming) -> Self {
+        BodyReader {
+            reader,
+            is_chunked: framing.is_chunked,
+            until_close: framing.is_close_delimited(),
+            remaining: match framing.content_length {
+                _ if framing.is_chunked => 0,
+                Some(len) => len as u64,
+                None => u64::MAX,
+            },
+            in_chunk: false,
+            done: false,
+            trailers: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        self.reader
+    }
+
+    /// Trailer fields of a chunked body, once it was read to its end.
+    pub fn take_trailers(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.trailers)
+    }
 
+    /// Moves past the end of the current chunk to the next one, or to the end of the body.
+    fn advance(&mut self) -> io::Result<()> {
+        if !self.is_chunked {
+            self.done = true;
+            return Ok(());
+        }
+
+        if self.in_chunk {
             let mut crlf = String::new();
-            reader.read_line(&mut crlf).map_err(|e| e.to_string())?;
-            body.extend(chunk_data);
+            self.reader.read_line(&mut crlf)?;
         }
-    } else {
-        body = vec![0u8; content_length];
-        reader
-            .read_exact(&mut body)
-            .map_err(|_| "Failed to read stream")?;
+        self.in_chunk = true;
+
+        let mut size_line = String::new();
+        self.reader.read_line(&mut size_line)?;
+        let size = size_line.split(';').next().unwrap_or("").trim();
+        self.remaining = u64::from_str_radix(size, 16)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Invalid chunk size"))?;
+
+        if self.remaining == 0 {
+            // Trailer fields run through the blank line ending the message
+            loop {
+                let mut line = String::new();
+                let read = self.reader.read_line(&mut line)?;
+                if read == 0 || line == "\r\n" {
+                    break;
+                }
+                if let Some((key, value)) = line.split_once(':') {
+                    self.trailers
+                        .push((key.trim().to_string(), value.trim().to_string()));
+                }
+            }
+            self.done = true;
+        }
+        Ok(())
     }
+}
 
-    Ok(format!(
-        "{}\r\n\r\n{}",
-        headers,
-        String::from_utf8_lossy(&body)
-    ))
+impl<R: BufRead> Read for BodyReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
 }
 
-pub fn parse_response(raw: &str) -> Result<Response, String> {
-    let (head, body) = raw.split_once("\r\n\r\n").ok_or("Malformed response!")?;
-    let mut response = Response {
-        status: 404,
-        headers: String::new(),
-        body: String::new(),
-    };
+impl<R: BufRead> BufRead for BodyReader<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.remaining == 0 && !self.done {
+            self.advance()?;
+        }
+        if self.done {
+            return Ok(&[]);
+        }
 
+        let until_close = self.until_close;
+        let buf = match self.reader.fill_buf() {
+            // TLS servers often close without a close_notify, which still ends the body
+            Err(err) if until_close && err.kind() == ErrorKind::UnexpectedEof => &[],
+            result => result?,
+        };
+        if buf.is_empty() {
+            if until_close {
+                self.done = true;
+                return Ok(&[]);
+            }
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "Connection closed mid body",
+            ));
+        }
+        let len = buf.len().min(self.remaining as usize);
+        Ok(&buf[..len])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt);
+        self.remaining -= amt as u64;
+    }
+}
+
+/// A decoded response body, whichever protocol version carries it.
+pub trait BodyStream: BufRead {
+    /// Bounds how long one read may wait, for bodies that never end on their own.
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Trailer fields that followed the body, once it was read to its end.
+    fn take_trailers(&mut self) -> Vec<(String, String)>;
+}
+
+/// Copies the body into `sink` as it arrives, returning the number of bytes written.
+pub fn copy_body(body: &mut dyn BufRead, sink: &mut dyn Write) -> Result<u64, String> {
+    let mut copied = 0;
+    loop {
+        let available = body
+            .fill_buf()
+            .map_err(|e| format!("Failed to read stream: {}", e))?;
+        if available.is_empty() {
+            return Ok(copied);
+        }
+
+        let len = available.len();
+        sink.write_all(available)
+            .map_err(|e| format!("Failed to write body: {}", e))?;
+        body.consume(len);
+        copied += len as u64;
+    }
+}
+
+/// Builds a response from its status line and headers only, leaving the body empty.
+pub fn parse_head(head: &str) -> Result<Response, String> {
     let mut head_lines = head.lines();
     let status_line = head_lines.next().ok_or("Empty Response")?;
-    let status = status_line
-        .split_whitespace()
-        .nth(1)
+    let mut parts = status_line.split_whitespace();
+    let version = parts.next().ok_or("Invalid status line")?;
+    let status = parts
+        .next()
         .ok_or("Invalid status line")?
         .parse::<u16>()
         .map_err(|_| "Invlid status code")?;
-    response.status = status;
 
-    let mut headers = String::new();
-    for line in head_lines {
-        headers.push_str(line);
-        headers.push_str("\r\n");
+    let headers = head_lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    Ok(Response::new(version, status, headers))
+}
+
+/// Fills in the body of a response built by `parse_head`, as its content type allows.
+pub fn decode_body(response: &mut Response, body: &str) -> Result<(), String> {
+    if body.is_empty() {
+        return Ok(());
     }
-    response.headers = headers;
 
     let content_type = response
         .get_header("content-type")
@@ -142,5 +686,225 @@ pub fn parse_response(raw: &str) -> Result<Response, String> {
         _ => return Err(format!("Unsupported content type found `{}`", mime_type)),
     };
 
-    Ok(response)
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head(request: &Request, body: &[u8]) -> String {
+        let body = Body::Bytes(body.to_vec());
+        let headers = request.headers(&body, "application/json", None);
+        request.encode_head("example.com", &headers)
+    }
+
+    fn request(method: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            url: String::from("/items"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn get_without_body_has_no_content_headers() {
+        assert_eq!(
+            head(&request("GET"), b""),
+            "GET /items HTTP/1.1\r\n\
+             Host: example.com\r\n\
+             Connection: keep-alive\r\n\
+             User-Agent: NetHop/0.0\r\n\
+             Accept: application/json\r\n\
+             Accept-Encoding: identity\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn post_without_body_announces_zero_length() {
+        assert!(head(&request("POST"), b"").contains("Content-Length: 0\r\n"));
+        assert!(!head(&request("POST"), b"").contains("Content-Type"));
+    }
+
+    #[test]
+    fn patch_and_delete_carry_their_body() {
+        for method in ["PATCH", "DELETE"] {
+            assert_eq!(
+                head(&request(method), b"{\"a\":1}"),
+                format!(
+                    "{} /items HTTP/1.1\r\n\
+                     Host: example.com\r\n\
+                     Connection: keep-alive\r\n\
+                     User-Agent: NetHop/0.0\r\n\
+                     Content-Type: application/json\r\n\
+                     Content-Length: 7\r\n\
+                     Accept: application/json\r\n\
+                     Accept-Encoding: identity\r\n\r\n",
+                    method
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn chunked_replaces_content_length() {
+        let request = Request {
+            chunked: true,
+            ..request("PUT")
+        };
+        let head = head(&request, b"data");
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!head.contains("Content-Length"));
+    }
+
+    #[test]
+    fn expect_only_goes_out_with_a_body() {
+        let request = Request {
+            expect_continue: true,
+            ..request("POST")
+        };
+        assert!(head(&request, b"data").ends_with("Expect: 100-continue\r\n\r\n"));
+        assert!(!head(&request, b"").contains("Expect"));
+    }
+
+    fn events(stream: &str) -> Vec<SseEvent> {
+        let mut parser = SseParser::default();
+        stream
+            .split_inclusive('\n')
+            .filter_map(|line| parser.feed(line))
+            .collect()
+    }
+
+    #[test]
+    fn sse_joins_data_lines() {
+        let events = events("event: update\r\ndata: a\r\ndata:b\r\nid: 7\r\nretry: 500\r\n\r\n");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "update");
+        assert_eq!(events[0].data, "a\nb");
+        assert_eq!(events[0].id.as_deref(), Some("7"));
+        assert_eq!(events[0].retry, Some(500));
+    }
+
+    #[test]
+    fn sse_skips_comments_and_events_without_data() {
+        let events = events(": ping\n\nevent: empty\n\ndata\nretry: soon\n\n");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "message");
+        assert_eq!(events[0].data, "");
+        assert_eq!(events[0].retry, None);
+    }
+
+    fn read_body(raw: &[u8], framing: &BodyFraming) -> (String, Vec<(String, String)>, String) {
+        let mut reader = io::Cursor::new(raw);
+        let mut body = BodyReader::new(&mut reader, framing);
+        let mut text = String::new();
+        body.read_to_string(&mut text).unwrap();
+        let trailers = body.take_trailers();
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        (text, trailers, rest)
+    }
+
+    #[test]
+    fn chunked_body_keeps_its_trailers() {
+        let framing = BodyFraming {
+            content_length: None,
+            is_chunked: true,
+        };
+        let (body, trailers, rest) = read_body(
+            b"4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\n\
+            Digest: sha-256=abc\r\nExpires: never\r\n\r\nNEXT",
+            &framing,
+        );
+        assert_eq!(body, "Wikipedia");
+        assert_eq!(
+            trailers,
+            [
+                (String::from("Digest"), String::from("sha-256=abc")),
+                (String::from("Expires"), String::from("never"))
+            ]
+        );
+        assert_eq!(rest, "NEXT");
+    }
+
+    #[test]
+    fn chunked_body_rejects_bad_sizes_and_early_close() {
+        let framing = BodyFraming {
+            content_length: None,
+            is_chunked: true,
+        };
+        let mut body = String::new();
+        let mut reader = io::Cursor::new(&b"zz\r\n"[..]);
+        assert!(
+            BodyReader::new(&mut reader, &framing)
+                .read_to_string(&mut body)
+                .is_err()
+        );
+        let mut reader = io::Cursor::new(&b"a\r\nshort"[..]);
+        assert!(
+            BodyReader::new(&mut reader, &framing)
+                .read_to_string(&mut body)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn body_without_length_runs_until_close() {
+        let framing = BodyFraming {
+            content_length: None,
+            is_chunked: false,
+        };
+        assert!(framing.is_close_delimited());
+        let (body, trailers, _) = read_body(b"all of it", &framing);
+        assert_eq!(body, "all of it");
+        assert!(trailers.is_empty());
+    }
+
+    #[test]
+    fn body_with_length_stops_at_it() {
+        let framing = BodyFraming {
+            content_length: Some(3),
+            is_chunked: false,
+        };
+        assert_eq!(
+            read_body(b"abcdef", &framing),
+            (String::from("abc"), Vec::new(), String::from("def"))
+        );
+        let mut body = String::new();
+        let mut reader = io::Cursor::new(&b"ab"[..]);
+        assert!(
+            BodyReader::new(&mut reader, &framing)
+                .read_to_string(&mut body)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn bodiless_responses() {
+        assert!(response_has_body("GET", 200));
+        assert!(response_has_body("OPTIONS", 200));
+        assert!(response_has_body("GET", 205));
+        assert!(!response_has_body("HEAD", 200));
+        assert!(!response_has_body("head", 404));
+        assert!(!response_has_body("GET", 103));
+        assert!(!response_has_body("DELETE", 204));
+        assert!(!response_has_body("GET", 304));
+    }
+
+    #[test]
+    fn head_skips_interim_responses() {
+        let mut reader = io::Cursor::new(
+            &b"HTTP/1.1 100 Continue\r\n\r\n\
+            HTTP/1.1 103 Early Hints\r\nLink: </style.css>\r\n\r\n\
+            HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"[..],
+        );
+        let (head, framing) =
+            read_head(&mut reader, Instant::now(), &mut Timings::default()).unwrap();
+        assert_eq!(head, "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n");
+        assert_eq!(framing.content_length, Some(2));
+
+        let mut reader = io::Cursor::new(&b"HTTP/1.1 101 Switching Protocols\r\n\r\n"[..]);
+        let (head, _) = read_head(&mut reader, Instant::now(), &mut Timings::default()).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 "));
+    }
 }
@@ -0,0 +1,1140 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, ErrorKind, Read, Write},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use hpack::{Decoder, Encoder};
+
+use crate::{
+    http::{Body, BodyStream},
+    network::Stream,
+};
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+const PROTOCOL_ERROR: u32 = 0x1;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const CANCEL: u32 = 0x8;
+
+/// Window sizes every endpoint starts with, and the frame size we accept since we never raise it.
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_FRAME_SIZE: usize = 16_384;
+/// Largest SETTINGS_MAX_FRAME_SIZE and SETTINGS_INITIAL_WINDOW_SIZE a server may announce.
+const MAX_ANNOUNCED_FRAME_SIZE: u32 = (1 << 24) - 1;
+const MAX_WINDOW: u32 = (1 << 31) - 1;
+/// Flow-control windows we grant the server, per stream and for the whole connection.
+const STREAM_WINDOW: u32 = 1 << 20;
+const CONNECTION_WINDOW: u32 = 1 << 24;
+/// How long one waiting query holds the socket before giving other streams a turn.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// How long an upload waits for the server to open its flow-control window again.
+const WINDOW_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn error_name(code: u32) -> String {
+    let name = match code {
+        0x0 => "NO_ERROR",
+        0x1 => "PROTOCOL_ERROR",
+        0x2 => "INTERNAL_ERROR",
+        0x3 => "FLOW_CONTROL_ERROR",
+        0x5 => "STREAM_CLOSED",
+        0x6 => "FRAME_SIZE_ERROR",
+        0x7 => "REFUSED_STREAM",
+        0x8 => "CANCEL",
+        0x9 => "COMPRESSION_ERROR",
+        0xb => "ENHANCE_YOUR_CALM",
+        0xd => "HTTP_1_1_REQUIRED",
+        _ => return format!("error code {:#x}", code),
+    };
+    name.to_string()
+}
+
+fn u32_at(payload: &[u8], at: usize) -> Option<u32> {
+    let bytes = payload.get(at..at + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// What the session knows of one request's response so far.
+struct StreamState {
+    status: Option<u16>,
+    headers: Vec<(String, String)>,
+    /// Body bytes received and not read yet.
+    data: Vec<u8>,
+    trailers: Vec<(String, String)>,
+    ended: bool,
+    error: Option<String>,
+    /// Bytes of request body the server is ready to take on this stream.
+    send_window: i64,
+}
+
+struct SessionState {
+    stream: Stream,
+    /// Bytes read off the socket that do not make a whole frame yet.
+    input: Vec<u8>,
+    encoder: Encoder<'static>,
+    decoder: Decoder<'static>,
+    next_id: u32,
+    streams: HashMap<u32, StreamState>,
+    /// Header block being assembled from a HEADERS frame and its CONTINUATION frames.
+    pending_block: Option<(u32, Vec<u8>, bool)>,
+    send_window: i64,
+    /// Window every new stream starts with, as set by the server.
+    initial_window: i64,
+    max_frame_size: usize,
+    max_streams: usize,
+    /// Last stream the server still processes after sending GOAWAY.
+    last_stream: Option<u32>,
+    /// Why the whole connection stopped working.
+    error: Option<String>,
+}
+
+impl SessionState {
+    /// Marks the connection as broken, returning the message for the caller to fail with.
+    fn fail(&mut self, message: String) -> String {
+        self.error.get_or_insert(message).clone()
+    }
+
+    /// Tells the server why the connection ends, then marks it as broken.
+    fn go_away(&mut self, code: u32, message: String) -> String {
+        let mut payload = Vec::with_capacity(8);
+        // We never accept streams the server opens, so none of them was processed
+        payload.extend_from_slice(&0u32.to_be_bytes());
+        payload.extend_from_slice(&code.to_be_bytes());
+        let _ = self.write_frame(GOAWAY, 0, 0, &payload);
+        self.fail(message)
+    }
+
+    fn write_frame(&mut self, kind: u8, flags: u8, id: u32, payload: &[u8]) -> Result<(), String> {
+        let len = (payload.len() as u32).to_be_bytes();
+        let mut frame = Vec::with_capacity(9 + payload.len());
+        frame.extend_from_slice(&len[1..]);
+        frame.push(kind);
+        frame.push(flags);
+        frame.extend_from_slice(&id.to_be_bytes());
+        frame.extend_from_slice(payload);
+
+        self.stream
+            .write_all(&frame)
+            .and_then(|_| self.stream.flush())
+            .map_err(|err| self.fail(format!("Failed to send HTTP/2 frame: {}", err)))
+    }
+
+    /// Gives back flow-control credit for body bytes we are done with.
+    fn credit(&mut self, id: Option<u32>, amount: usize) -> Result<(), String> {
+        if amount == 0 {
+            return Ok(());
+        }
+        let increment = (amount as u32).to_be_bytes();
+        self.write_frame(WINDOW_UPDATE, 0, 0, &increment)?;
+        match id {
+            Some(id) => self.write_frame(WINDOW_UPDATE, 0, id, &increment),
+            None => Ok(()),
+        }
+    }
+
+    fn active_streams(&self) -> usize {
+        self.streams
+            .values()
+            .filter(|stream| !stream.ended && stream.error.is_none())
+            .count()
+    }
+
+    /// Reads what the socket has within the poll interval and handles every whole frame.
+    fn pump(&mut self) -> Result<(), String> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+
+        let mut buffer = [0u8; MAX_FRAME_SIZE];
+        match self.stream.read(&mut buffer) {
+            Ok(0) => return Err(self.fail("Connection closed by server".to_string())),
+            Ok(read) => self.input.extend_from_slice(&buffer[..read]),
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(());
+            }
+            Err(err) => return Err(self.fail(format!("Failed to read stream: {}", err))),
+        }
+
+        while self.input.len() >= 9 {
+            let len = usize::from(self.input[0]) << 16
+                | usize::from(self.input[1]) << 8
+                | usize::from(self.input[2]);
+            if len > MAX_FRAME_SIZE {
+                return Err(self.fail(format!("Server sent a {} byte frame", len)));
+            }
+            if self.input.len() < 9 + len {
+                break;
+            }
+
+            let frame: Vec<u8> = self.input.drain(..9 + len).collect();
+            let id = u32_at(&frame, 5).unwrap_or(0) & 0x7fff_ffff;
+            self.process(frame[3], frame[4], id, &frame[9..])?;
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, kind: u8, flags: u8, id: u32, payload: &[u8]) -> Result<(), String> {
+        if self.pending_block.is_some() && kind != CONTINUATION {
+            return Err(self.fail("Header block interrupted by another frame".to_string()));
+        }
+
+        match kind {
+            DATA => {
+                let data = self.unpad(flags, payload)?;
+                match self.streams.get_mut(&id) {
+                    Some(stream) if !stream.ended => {
+                        stream.data.extend_from_slice(data);
+                        stream.ended = flags & END_STREAM != 0;
+                        // The data itself is credited back once read
+                        self.credit(None, payload.len() - data.len())?;
+                    }
+                    // Still in flight when its stream was cancelled
+                    _ => self.credit(None, payload.len())?,
+                }
+            }
+            HEADERS => {
+                let mut block = self.unpad(flags, payload)?;
+                if flags & PRIORITY != 0 {
+                    block = block.get(5..).unwrap_or_default();
+                }
+                let block = block.to_vec();
+                let end_stream = flags & END_STREAM != 0;
+                if flags & END_HEADERS != 0 {
+                    self.receive_headers(id, &block, end_stream)?;
+                } else {
+                    self.pending_block = Some((id, block, end_stream));
+                }
+            }
+            CONTINUATION => {
+                let Some((block_id, mut block, end_stream)) = self.pending_block.take() else {
+                    return Err(self.fail("Unexpected CONTINUATION frame".to_string()));
+                };
+                if block_id != id {
+                    return Err(self.fail("CONTINUATION frame on another stream".to_string()));
+                }
+                block.extend_from_slice(payload);
+                if flags & END_HEADERS != 0 {
+                    self.receive_headers(id, &block, end_stream)?;
+                } else {
+                    self.pending_block = Some((id, block, end_stream));
+                }
+            }
+            RST_STREAM => {
+                let code = u32_at(payload, 0).unwrap_or(0);
+                if let Some(stream) = self.streams.get_mut(&id)
+                    && !stream.ended
+                {
+                    stream.error = Some(format!("Stream reset by server: {}", error_name(code)));
+                }
+            }
+            SETTINGS if flags & ACK == 0 => {
+                self.apply_settings(payload)?;
+                self.write_frame(SETTINGS, ACK, 0, &[])?;
+            }
+            PING if flags & ACK == 0 => self.write_frame(PING, ACK, 0, payload)?,
+            GOAWAY => {
+                let last = u32_at(payload, 0).unwrap_or(0) & 0x7fff_ffff;
+                let code = u32_at(payload, 4).unwrap_or(0);
+                self.last_stream = Some(last);
+                for (_, stream) in self.streams.iter_mut().filter(|(id, _)| **id > last) {
+                    stream.error = Some(format!("Server went away: {}", error_name(code)));
+                }
+            }
+            WINDOW_UPDATE => {
+                let increment = i64::from(u32_at(payload, 0).unwrap_or(0) & 0x7fff_ffff);
+                if id == 0 {
+                    self.send_window += increment;
+                } else if let Some(stream) = self.streams.get_mut(&id) {
+                    stream.send_window += increment;
+                }
+            }
+            PUSH_PROMISE => {
+                return Err(self.fail("Server pushed a stream though push is off".to_string()));
+            }
+            // PRIORITY, acks and frame types we do not know are ignored
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The payload of a frame without its padding.
+    fn unpad<'a>(&mut self, flags: u8, payload: &'a [u8]) -> Result<&'a [u8], String> {
+        if flags & PADDED == 0 {
+            return Ok(payload);
+        }
+        let padding = usize::from(payload.first().copied().unwrap_or(0));
+        match payload.len().checked_sub(padding + 1) {
+            Some(len) => Ok(&payload[1..1 + len]),
+            None => Err(self.fail("Frame padding exceeds its payload".to_string())),
+        }
+    }
+
+    fn receive_headers(&mut self, id: u32, block: &[u8], end_stream: bool) -> Result<(), String> {
+        // Decoded even for cancelled streams, the header table must stay in step with the server
+        let fields = match self.decoder.decode(block) {
+            Ok(fields) => fields,
+            Err(err) => return Err(self.fail(format!("Failed to decode headers: {:?}", err))),
+        };
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return Ok(());
+        };
+
+        let mut status = None;
+        let mut headers = Vec::new();
+        for (name, value) in fields {
+            let name = String::from_utf8_lossy(&name).into_owned();
+            let value = String::from_utf8_lossy(&value).into_owned();
+            if name == ":status" {
+                status = value.parse::<u16>().ok();
+            } else if !name.starts_with(':') {
+                headers.push((name, value));
+            }
+        }
+
+        match (stream.status, status) {
+            // Interim responses have nothing we report
+            (None, Some(100..=199)) => {}
+            (None, Some(status)) => {
+                stream.status = Some(status);
+                stream.headers = headers;
+            }
+            (None, None) => stream.error = Some("Response without a :status".to_string()),
+            (Some(_), _) => stream.trailers.extend(headers),
+        }
+        if end_stream {
+            stream.ended = true;
+        }
+        Ok(())
+    }
+
+    fn apply_settings(&mut self, payload: &[u8]) -> Result<(), String> {
+        if !payload.len().is_multiple_of(6) {
+            return Err(self.fail("Malformed SETTINGS frame".to_string()));
+        }
+
+        for setting in payload.chunks_exact(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32_at(setting, 2).unwrap_or(0);
+            match id {
+                SETTINGS_MAX_CONCURRENT_STREAMS => self.max_streams = value as usize,
+                SETTINGS_INITIAL_WINDOW_SIZE if value > MAX_WINDOW => {
+                    return Err(self.go_away(
+                        FLOW_CONTROL_ERROR,
+                        format!("Server announced an initial window of {} bytes", value),
+                    ));
+                }
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let delta = i64::from(value) - self.initial_window;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                    }
+                    self.initial_window = i64::from(value);
+                }
+                SETTINGS_MAX_FRAME_SIZE
+                    if !(MAX_FRAME_SIZE as u32..=MAX_ANNOUNCED_FRAME_SIZE).contains(&value) =>
+                {
+                    return Err(self.go_away(
+                        PROTOCOL_ERROR,
+                        format!("Server announced a maximum frame size of {} bytes", value),
+                    ));
+                }
+                SETTINGS_MAX_FRAME_SIZE => self.max_frame_size = value as usize,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a wait for response body data ended with.
+pub enum Chunk {
+    Data(Vec<u8>),
+    /// The stream ended, with the trailers the server sent.
+    End(Vec<(String, String)>),
+    TimedOut,
+}
+
+/// One HTTP/2 connection, which any number of queries can send requests over at once.
+///
+/// There is no background thread: whichever query is waiting on the session reads the socket
+/// and files every frame under its stream, for the query owning it to pick up.
+pub struct H2Session {
+    state: Mutex<SessionState>,
+}
+
+impl H2Session {
+    /// Sends the connection preface and our settings over a freshly opened stream.
+    pub fn start(mut stream: Stream) -> Result<H2Session, String> {
+        // Frames go out in small separate writes that Nagle's algorithm would hold back
+        stream
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .and_then(|_| stream.set_nodelay(true))
+            .map_err(|err| err.to_string())?;
+        stream
+            .write_all(PREFACE)
+            .map_err(|err| format!("Failed to send HTTP/2 preface: {}", err))?;
+
+        let mut state = SessionState {
+            stream,
+            input: Vec::new(),
+            encoder: Encoder::new(),
+            decoder: Decoder::new(),
+            next_id: 1,
+            streams: HashMap::new(),
+            pending_block: None,
+            send_window: DEFAULT_WINDOW,
+            initial_window: DEFAULT_WINDOW,
+            max_frame_size: MAX_FRAME_SIZE,
+            max_streams: usize::MAX,
+            last_stream: None,
+            error: None,
+        };
+
+        let mut settings = Vec::new();
+        for (id, value) in [
+            (SETTINGS_ENABLE_PUSH, 0),
+            (SETTINGS_INITIAL_WINDOW_SIZE, STREAM_WINDOW),
+        ] {
+            settings.extend_from_slice(&id.to_be_bytes());
+            settings.extend_from_slice(&u32::to_be_bytes(value));
+        }
+        state.write_frame(SETTINGS, 0, 0, &settings)?;
+        let increment = CONNECTION_WINDOW - DEFAULT_WINDOW as u32;
+        state.write_frame(WINDOW_UPDATE, 0, 0, &increment.to_be_bytes())?;
+
+        Ok(H2Session {
+            state: Mutex::new(state),
+        })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, SessionState>, String> {
+        self.state
+            .lock()
+            .map_err(|_| "HTTP/2 session is unavailable".to_string())
+    }
+
+    /// Whether new requests can still go over this connection.
+    pub fn is_usable(&self) -> bool {
+        self.lock()
+            .is_ok_and(|state| state.error.is_none() && state.last_stream.is_none())
+    }
+
+    /// Reads frames until `ready` has an answer, letting go of the socket between reads so
+    /// queries waiting on other streams get their turn.
+    fn wait_for<T>(
+        &self,
+        mut ready: impl FnMut(&mut SessionState) -> Option<Result<T, String>>,
+    ) -> Result<T, String> {
+        loop {
+            let mut state = self.lock()?;
+            if let Some(result) = ready(&mut state) {
+                return result;
+            }
+            state.pump()?;
+            drop(state);
+            thread::yield_now();
+        }
+    }
+
+    /// Opens a stream carrying the request and uploads its body. Returns the stream id.
+    pub fn send(
+        &self,
+        pseudo_headers: [(&str, &str); 4],
+        headers: &[(String, String)],
+        body: &Body,
+    ) -> Result<u32, String> {
+        let mut fields: Vec<(Vec<u8>, Vec<u8>)> = pseudo_headers
+            .iter()
+            .map(|(name, value)| (name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect();
+        fields.extend(headers.iter().map(|(name, value)| {
+            (
+                name.to_ascii_lowercase().into_bytes(),
+                value.as_bytes().to_vec(),
+            )
+        }));
+
+        // Wait for one of the streams the server allows at once to free up
+        let id = self.wait_for(|state| {
+            if let Some(error) = &state.error {
+                return Some(Err(error.clone()));
+            }
+            if state.last_stream.is_some() {
+                return Some(Err("Server is going away".to_string()));
+            }
+            if state.active_streams() >= state.max_streams {
+                return None;
+            }
+
+            let id = state.next_id;
+            state.next_id += 2;
+            let stream = StreamState {
+                status: None,
+                headers: Vec::new(),
+                data: Vec::new(),
+                trailers: Vec::new(),
+                ended: false,
+                error: None,
+                send_window: state.initial_window,
+            };
+            state.streams.insert(id, stream);
+
+            let block = state.encoder.encode(&fields);
+            let fragments: Vec<&[u8]> = block.chunks(state.max_frame_size).collect();
+            let fragments = if fragments.is_empty() {
+                vec![&[][..]]
+            } else {
+                fragments
+            };
+            for (index, fragment) in fragments.iter().enumerate() {
+                let mut flags = 0;
+                if index + 1 == fragments.len() {
+                    flags |= END_HEADERS;
+                }
+                let kind = if index == 0 {
+                    if body.is_empty() {
+                        flags |= END_STREAM;
+                    }
+                    HEADERS
+                } else {
+                    CONTINUATION
+                };
+                if let Err(err) = state.write_frame(kind, flags, id, fragment) {
+                    return Some(Err(err));
+                }
+            }
+            Some(Ok(id))
+        })?;
+
+        if !body.is_empty() {
+            self.upload(id, body)?;
+        }
+        Ok(id)
+    }
+
+    /// Sends the body as DATA frames as fast as the server's flow-control windows allow.
+    fn upload(&self, id: u32, body: &Body) -> Result<(), String> {
+        let mut source = body
+            .reader()
+            .map_err(|err| format!("Failed to write body: {}", err))?;
+        let mut buffer = vec![0u8; MAX_FRAME_SIZE];
+        let mut progressed = Instant::now();
+
+        loop {
+            let read = source
+                .read(&mut buffer)
+                .map_err(|err| format!("Failed to write body: {}", err))?;
+            let mut chunk = &buffer[..read];
+
+            loop {
+                let sent = self.wait_for(|state| {
+                    let max_frame_size = state.max_frame_size as i64;
+                    let connection_window = state.send_window;
+                    // The server answered early or gave up on the body, its response tells why
+                    let Some(stream) = state
+                        .streams
+                        .get_mut(&id)
+                        .filter(|stream| stream.error.is_none() && !stream.ended)
+                    else {
+                        return Some(Ok(None));
+                    };
+
+                    let window = connection_window
+                        .min(stream.send_window)
+                        .min(max_frame_size);
+                    if window <= 0 && !chunk.is_empty() {
+                        if progressed.elapsed() < WINDOW_TIMEOUT {
+                            return None;
+                        }
+                        state.streams.remove(&id);
+                        let _ = state.write_frame(RST_STREAM, 0, id, &CANCEL.to_be_bytes());
+                        return Some(Err(format!(
+                            "Server opened no flow-control window for {}s",
+                            WINDOW_TIMEOUT.as_secs()
+                        )));
+                    }
+                    let len = chunk.len().min(window.max(0) as usize);
+                    stream.send_window -= len as i64;
+                    state.send_window -= len as i64;
+
+                    let flags = if read == 0 { END_STREAM } else { 0 };
+                    Some(
+                        state
+                            .write_frame(DATA, flags, id, &chunk[..len])
+                            .map(|_| Some(len)),
+                    )
+                })?;
+
+                match sent {
+                    Some(len) => {
+                        chunk = &chunk[len..];
+                        progressed = Instant::now();
+                    }
+                    None => return Ok(()),
+                }
+                if chunk.is_empty() {
+                    break;
+                }
+            }
+
+            if read == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Waits for the final response head of stream `id`.
+    pub fn read_head(&self, id: u32) -> Result<(u16, Vec<(String, String)>), String> {
+        self.wait_for(|state| {
+            let Some(stream) = state.streams.get(&id) else {
+                return Some(Err("Unknown HTTP/2 stream".to_string()));
+            };
+            if let Some(status) = stream.status {
+                return Some(Ok((status, stream.headers.clone())));
+            }
+            let error = stream.error.clone()?;
+            state.streams.remove(&id);
+            Some(Err(error))
+        })
+    }
+
+    /// Takes the body data received on stream `id`, waiting for some until `deadline`.
+    pub fn read_data(&self, id: u32, deadline: Option<Instant>) -> Result<Chunk, String> {
+        self.wait_for(|state| {
+            let Some(stream) = state.streams.get_mut(&id) else {
+                return Some(Ok(Chunk::End(Vec::new())));
+            };
+
+            if !stream.data.is_empty() {
+                let data = std::mem::take(&mut stream.data);
+                // A stream the server ended takes no more updates
+                let stream_id = (!stream.ended).then_some(id);
+                return Some(
+                    state
+                        .credit(stream_id, data.len())
+                        .map(|_| Chunk::Data(data)),
+                );
+            }
+            if stream.ended {
+                let trailers = std::mem::take(&mut stream.trailers);
+                state.streams.remove(&id);
+                return Some(Ok(Chunk::End(trailers)));
+            }
+            if let Some(error) = stream.error.clone() {
+                state.streams.remove(&id);
+                return Some(Err(error));
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Some(Ok(Chunk::TimedOut));
+            }
+            None
+        })
+    }
+
+    /// Stops the server sending the rest of a response we no longer want.
+    pub fn cancel(&self, id: u32) {
+        let Ok(mut state) = self.lock() else {
+            return;
+        };
+        if let Some(stream) = state.streams.remove(&id)
+            && !stream.ended
+            && stream.error.is_none()
+        {
+            let _ = state.write_frame(RST_STREAM, 0, id, &CANCEL.to_be_bytes());
+        }
+    }
+}
+
+/// The body of one response, read off its stream as the session delivers it.
+pub struct H2Body {
+    session: Arc<H2Session>,
+    id: u32,
+    buffer: Vec<u8>,
+    position: usize,
+    timeout: Option<Duration>,
+    done: bool,
+    trailers: Vec<(String, String)>,
+}
+
+impl H2Body {
+    pub fn new(session: Arc<H2Session>, id: u32) -> Self {
+        H2Body {
+            session,
+            id,
+            buffer: Vec::new(),
+            position: 0,
+            timeout: None,
+            done: false,
+            trailers: Vec::new(),
+        }
+    }
+}
+
+impl Read for H2Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for H2Body {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position == self.buffer.len() && !self.done {
+            let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+            match self
+                .session
+                .read_data(self.id, deadline)
+                .map_err(io::Error::other)?
+            {
+                Chunk::Data(data) => {
+                    self.buffer = data;
+                    self.position = 0;
+                }
+                Chunk::End(trailers) => {
+                    self.trailers = trailers;
+                    self.done = true;
+                }
+                Chunk::TimedOut => {
+                    return Err(io::Error::new(ErrorKind::TimedOut, "No data in time"));
+                }
+            }
+        }
+        Ok(&self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position += amt;
+    }
+}
+
+impl BodyStream for H2Body {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn take_trailers(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.trailers)
+    }
+}
+
+impl Drop for H2Body {
+    fn drop(&mut self) {
+        if !self.done {
+            self.session.cancel(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{TcpListener, TcpStream},
+        thread::JoinHandle,
+    };
+
+    use super::*;
+
+    /// The server end of a session, reading and writing whole frames.
+    struct Peer {
+        stream: TcpStream,
+        encoder: Encoder<'static>,
+        decoder: Decoder<'static>,
+    }
+
+    impl Peer {
+        fn read_frame(&mut self) -> (u8, u8, u32, Vec<u8>) {
+            let mut head = [0u8; 9];
+            self.stream.read_exact(&mut head).unwrap();
+            let len = usize::from(head[0]) << 16 | usize::from(head[1]) << 8 | usize::from(head[2]);
+            let mut payload = vec![0u8; len];
+            self.stream.read_exact(&mut payload).unwrap();
+            (head[3], head[4], u32_at(&head, 5).unwrap(), payload)
+        }
+
+        /// The next frame that is not a WINDOW_UPDATE or an acknowledgement.
+        fn read_request_frame(&mut self) -> (u8, u8, u32, Vec<u8>) {
+            loop {
+                let frame = self.read_frame();
+                match frame {
+                    (WINDOW_UPDATE, ..) => {}
+                    (SETTINGS | PING, flags, ..) if flags & ACK != 0 => {}
+                    frame => return frame,
+                }
+            }
+        }
+
+        fn write_frame(&mut self, kind: u8, flags: u8, id: u32, payload: &[u8]) {
+            let len = (payload.len() as u32).to_be_bytes();
+            let mut frame = len[1..].to_vec();
+            frame.extend_from_slice(&[kind, flags]);
+            frame.extend_from_slice(&id.to_be_bytes());
+            frame.extend_from_slice(payload);
+            self.stream.write_all(&frame).unwrap();
+        }
+
+        fn settings(&mut self, settings: &[(u16, u32)]) {
+            let mut payload = Vec::new();
+            for (id, value) in settings {
+                payload.extend_from_slice(&id.to_be_bytes());
+                payload.extend_from_slice(&value.to_be_bytes());
+            }
+            self.write_frame(SETTINGS, 0, 0, &payload);
+        }
+
+        fn header_block(&mut self, fields: &[(&str, &str)]) -> Vec<u8> {
+            let fields: Vec<(Vec<u8>, Vec<u8>)> = fields
+                .iter()
+                .map(|(name, value)| (name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+                .collect();
+            self.encoder.encode(&fields)
+        }
+
+        /// Reads the request HEADERS, returning its stream id and decoded fields.
+        fn read_request(&mut self) -> (u32, Vec<(String, String)>, bool) {
+            let (kind, flags, id, block) = self.read_request_frame();
+            assert_eq!(kind, HEADERS);
+            assert!(flags & END_HEADERS != 0);
+            let fields = self
+                .decoder
+                .decode(&block)
+                .unwrap()
+                .into_iter()
+                .map(|(name, value)| {
+                    (
+                        String::from_utf8(name).unwrap(),
+                        String::from_utf8(value).unwrap(),
+                    )
+                })
+                .collect();
+            (id, fields, flags & END_STREAM != 0)
+        }
+    }
+
+    /// A session whose server side plays `script` once the connection preface arrived.
+    fn session(script: impl FnOnce(&mut Peer) + Send + 'static) -> (H2Session, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut peer = Peer {
+                stream,
+                encoder: Encoder::new(),
+                decoder: Decoder::new(),
+            };
+            let mut preface = [0u8; PREFACE.len()];
+            peer.stream.read_exact(&mut preface).unwrap();
+            assert_eq!(preface, PREFACE);
+            let (kind, flags, _, settings) = peer.read_frame();
+            assert_eq!((kind, flags), (SETTINGS, 0));
+            assert_eq!(
+                settings,
+                [0, 2, 0, 0, 0, 0, 0, 4, 0, 0x10, 0, 0],
+                "push off and a 1 MiB stream window"
+            );
+            script(&mut peer);
+            // Stays open for whatever the client still sends, until it hangs up
+            let mut rest = Vec::new();
+            let _ = peer.stream.read_to_end(&mut rest);
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        (H2Session::start(Stream::Http(stream)).unwrap(), server)
+    }
+
+    fn get(session: &H2Session) -> Result<u32, String> {
+        session.send(
+            [
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":authority", "localhost:8080"),
+                (":path", "/"),
+            ],
+            &[(String::from("Accept"), String::from("*/*"))],
+            &Body::Bytes(Vec::new()),
+        )
+    }
+
+    /// Everything left of a response body, with its trailers.
+    fn read_body(session: &H2Session, id: u32) -> (Vec<u8>, Vec<(String, String)>) {
+        let mut body = Vec::new();
+        loop {
+            match session.read_data(id, None).unwrap() {
+                Chunk::Data(data) => body.extend(data),
+                Chunk::End(trailers) => return (body, trailers),
+                Chunk::TimedOut => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn acknowledges_settings_and_sends_requests() {
+        let (session, server) = session(|peer| {
+            peer.settings(&[(SETTINGS_MAX_CONCURRENT_STREAMS, 10)]);
+            let (kind, flags, _, _) = peer.read_frame();
+            assert_eq!((kind, flags), (WINDOW_UPDATE, 0));
+            let (kind, flags, _, payload) = peer.read_frame();
+            assert_eq!((kind, flags, payload.len()), (SETTINGS, ACK, 0));
+
+            let (id, fields, end_stream) = peer.read_request();
+            assert_eq!(id, 1);
+            assert!(end_stream);
+            assert_eq!(
+                fields,
+                [
+                    (String::from(":method"), String::from("GET")),
+                    (String::from(":scheme"), String::from("http")),
+                    (String::from(":authority"), String::from("localhost:8080")),
+                    (String::from(":path"), String::from("/")),
+                    (String::from("accept"), String::from("*/*")),
+                ]
+            );
+            let block = peer.header_block(&[(":status", "204")]);
+            peer.write_frame(HEADERS, END_HEADERS | END_STREAM, id, &block);
+        });
+
+        // Takes in the server settings before the request goes out
+        thread::sleep(Duration::from_millis(50));
+        session.lock().unwrap().pump().unwrap();
+        let id = get(&session).unwrap();
+        assert_eq!(session.read_head(id).unwrap(), (204, Vec::new()));
+        assert_eq!(read_body(&session, id), (Vec::new(), Vec::new()));
+        drop(session);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn joins_continuation_frames_and_reads_trailers() {
+        let (session, server) = session(|peer| {
+            let (id, _, _) = peer.read_request();
+            let block = peer.header_block(&[
+                (":status", "200"),
+                ("content-type", "text/plain"),
+                ("x-long", &"a".repeat(100)),
+            ]);
+            let (first, rest) = block.split_at(10);
+            peer.write_frame(HEADERS, 0, id, first);
+            peer.write_frame(CONTINUATION, END_HEADERS, id, rest);
+            // Padded with 3 bytes after a length byte
+            peer.write_frame(DATA, PADDED, id, b"\x03hello\0\0\0");
+            peer.write_frame(DATA, 0, id, b" world");
+            let block = peer.header_block(&[("grpc-status", "0")]);
+            peer.write_frame(HEADERS, END_HEADERS | END_STREAM, id, &block);
+        });
+
+        let id = get(&session).unwrap();
+        let (status, headers) = session.read_head(id).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(
+            headers[0],
+            (String::from("content-type"), String::from("text/plain"))
+        );
+        assert_eq!(headers[1].1.len(), 100);
+        assert_eq!(
+            read_body(&session, id),
+            (
+                b"hello world".to_vec(),
+                vec![(String::from("grpc-status"), String::from("0"))]
+            )
+        );
+        drop(session);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn uploads_within_the_flow_control_window() {
+        let (session, server) = session(|peer| {
+            peer.settings(&[(SETTINGS_INITIAL_WINDOW_SIZE, 10)]);
+            let (id, _, end_stream) = peer.read_request();
+            assert!(!end_stream);
+
+            let mut received = Vec::new();
+            loop {
+                let (kind, flags, _, data) = peer.read_request_frame();
+                assert_eq!(kind, DATA);
+                assert!(data.len() <= 10);
+                received.extend(data);
+                if flags & END_STREAM != 0 {
+                    break;
+                }
+                if received.len() % 10 == 0 && !received.is_empty() {
+                    peer.write_frame(WINDOW_UPDATE, 0, id, &10u32.to_be_bytes());
+                }
+            }
+            assert_eq!(received, b"abcdefghijklmnopqrstuvwxy");
+
+            let block = peer.header_block(&[(":status", "200")]);
+            peer.write_frame(HEADERS, END_HEADERS, id, &block);
+            peer.write_frame(DATA, END_STREAM, id, b"ok");
+        });
+
+        thread::sleep(Duration::from_millis(50));
+        session.lock().unwrap().pump().unwrap();
+        let id = session
+            .send(
+                [
+                    (":method", "POST"),
+                    (":scheme", "http"),
+                    (":authority", "localhost"),
+                    (":path", "/upload"),
+                ],
+                &[],
+                &Body::Bytes(b"abcdefghijklmnopqrstuvwxy".to_vec()),
+            )
+            .unwrap();
+        assert_eq!(session.read_head(id).unwrap().0, 200);
+        assert_eq!(read_body(&session, id).0, b"ok");
+        drop(session);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn credits_back_received_data() {
+        let (session, server) = session(|peer| {
+            let (id, _, _) = peer.read_request();
+            let block = peer.header_block(&[(":status", "200")]);
+            peer.write_frame(HEADERS, END_HEADERS, id, &block);
+            peer.write_frame(DATA, 0, id, &[b'x'; 1000]);
+
+            let mut updates = Vec::new();
+            while updates.len() < 2 {
+                let (kind, _, stream, payload) = peer.read_frame();
+                if kind == WINDOW_UPDATE {
+                    updates.push((stream, u32_at(&payload, 0).unwrap()));
+                }
+            }
+            assert_eq!(updates, [(0, 1000), (id, 1000)]);
+            peer.write_frame(DATA, END_STREAM, id, b"");
+        });
+
+        let id = get(&session).unwrap();
+        session.read_head(id).unwrap();
+        assert_eq!(read_body(&session, id).0.len(), 1000);
+        drop(session);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reports_reset_streams() {
+        let (session, server) = session(|peer| {
+            let (id, _, _) = peer.read_request();
+            peer.write_frame(RST_STREAM, 0, id, &0x7u32.to_be_bytes());
+        });
+
+        let id = get(&session).unwrap();
+        assert_eq!(
+            session.read_head(id),
+            Err(String::from("Stream reset by server: REFUSED_STREAM"))
+        );
+        assert!(session.is_usable());
+        drop(session);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn finishes_streams_below_goaway() {
+        let (session, server) = session(|peer| {
+            let (first, _, _) = peer.read_request();
+            let (second, _, _) = peer.read_request();
+            let mut goaway = first.to_be_bytes().to_vec();
+            goaway.extend_from_slice(&0u32.to_be_bytes());
+            peer.write_frame(GOAWAY, 0, 0, &goaway);
+
+            let block = peer.header_block(&[(":status", "200")]);
+            peer.write_frame(HEADERS, END_HEADERS | END_STREAM, first, &block);
+            assert_eq!(second, 3);
+        });
+
+        let first = get(&session).unwrap();
+        let second = get(&session).unwrap();
+        assert_eq!(
+            session.read_head(second),
+            Err(String::from("Server went away: NO_ERROR"))
+        );
+        assert_eq!(session.read_head(first).unwrap().0, 200);
+        assert!(!session.is_usable());
+        assert_eq!(get(&session), Err(String::from("Server is going away")));
+        drop(session);
+        server.join().unwrap();
+    }
+
+    fn rejects_setting(id: u16, value: u32, code: u32, message: &str) {
+        let (session, server) = session(move |peer| {
+            peer.settings(&[(id, value)]);
+            loop {
+                let (kind, _, _, payload) = peer.read_frame();
+                if kind == GOAWAY {
+                    assert_eq!(u32_at(&payload, 4), Some(code));
+                    return;
+                }
+            }
+        });
+
+        thread::sleep(Duration::from_millis(50));
+        let error = loop {
+            if let Err(error) = session.lock().unwrap().pump() {
+                break error;
+            }
+        };
+        assert_eq!(error, message);
+        assert!(!session.is_usable());
+        assert_eq!(get(&session), Err(String::from(message)));
+        drop(session);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn rejects_frame_sizes_out_of_range() {
+        rejects_setting(
+            SETTINGS_MAX_FRAME_SIZE,
+            0,
+            PROTOCOL_ERROR,
+            "Server announced a maximum frame size of 0 bytes",
+        );
+        rejects_setting(
+            SETTINGS_MAX_FRAME_SIZE,
+            1 << 24,
+            PROTOCOL_ERROR,
+            "Server announced a maximum frame size of 16777216 bytes",
+        );
+    }
+
+    #[test]
+    fn rejects_windows_out_of_range() {
+        rejects_setting(
+            SETTINGS_INITIAL_WINDOW_SIZE,
+            1 << 31,
+            FLOW_CONTROL_ERROR,
+            "Server announced an initial window of 2147483648 bytes",
+        );
+    }
+}
@@ -1,36 +1,55 @@
+mod auth;
+mod bench;
+mod cli;
 mod compiler;
+mod cookies;
+mod diff;
 mod file_handler;
+mod forms;
+mod graphql;
 mod hop_lang;
 mod http;
+mod http2;
 mod network;
+mod oauth2;
+mod report;
+mod rpc;
+mod schema;
+mod snapshot;
 mod test_bed;
 mod ui;
+mod util;
+mod variables;
+mod websocket;
 
 use std::{
     env,
     error::Error,
     io::{self, Write},
+    path::Path,
 };
 
 use crate::{
+    bench::run_bench,
+    cli::{Command, parse_args},
     compiler::lexer::Lexer,
     file_handler::{read_queries_from_file, read_queries_from_workspace},
-    hop_lang::{clean_script, fetch_connection_header, fetch_requests},
-    network::{connect, execute_batch_requests},
+    hop_lang::{fetch_connection_header, fetch_requests},
+    network::{execute_batch_requests, execute_parallel_requests},
 };
 
 fn main() -> Result<(), Box<dyn Error>> {
     println!("🐇 NetHop v0.1-Beta");
 
-    let args: Vec<String> = env::args().collect();
-    let query_raw = match args.get(1) {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = parse_args(&args)?;
+    let query_raw = match &options.file_path {
         Some(file_path) => read_queries_from_file(file_path)?,
         None => read_queries_from_workspace()?,
     };
 
-    let cleaned_queries = clean_script(&query_raw)?;
     let mut lexer = Lexer {
-        input: query_raw,
+        input: query_raw.clone(),
         position: 0,
     };
 
@@ -43,10 +62,40 @@ fn main() -> Result<(), Box<dyn Error>> {
     io::stdin().read_line(&mut start_query)?;
 
     if start_query.trim().to_lowercase() == "y" {
-        let mut conn = fetch_connection_header(&cleaned_queries)?;
-        connect(&mut conn)?;
-        let all_requests = fetch_requests(&cleaned_queries)?;
-        execute_batch_requests(all_requests, &mut conn)?;
+        let mut conn = fetch_connection_header(&query_raw)?;
+        let base_dir = match &options.file_path {
+            Some(file_path) => Path::new(file_path)
+                .parent()
+                .map(|dir| dir.to_path_buf())
+                .unwrap_or_default(),
+            None => env::current_dir()?.join(".nethop"),
+        };
+        let mut all_requests = fetch_requests(&query_raw, &base_dir)?;
+        if options.update_snapshots {
+            for snapshot in all_requests.iter_mut().filter_map(|r| r.snapshot.as_mut()) {
+                snapshot.update = true;
+            }
+        }
+
+        if let Command::Bench(bench_options) = options.command {
+            run_bench(all_requests, &conn, bench_options);
+            return Ok(());
+        }
+
+        let report = if options.jobs > 1 {
+            execute_parallel_requests(all_requests, &conn, options.policy, options.jobs)
+        } else {
+            execute_batch_requests(all_requests, &mut conn, options.policy)
+        };
+        report.print_summary();
+
+        if let Ok(jar) = conn.cookies.lock() {
+            jar.save()?;
+        }
+
+        if !report.is_success() {
+            return Err("Some queries did not pass".into());
+        }
     } else {
         println!("❌ Cancelled");
     }
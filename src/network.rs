@@ -1,15 +1,32 @@
 use std::{
     error::Error,
-    io::{BufReader, Read, Write},
-    net::TcpStream,
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::{Duration, Instant},
 };
 
 use native_tls::{TlsConnector, TlsStream};
 
 use crate::{
-    http::{Request, Response, parse_response, read_body},
+    auth::{Auth, answer_digest, digest_challenge},
+    cookies::CookieJar,
+    graphql::check_errors,
+    http::{
+        Body, BodyFraming, BodyReader, BodyStream, EventLimit, Request, Response, SseEvent,
+        SseParser, StreamTarget, Timings, copy_body, decode_body, parse_head, read_head,
+        response_has_body,
+    },
+    http2::{H2Body, H2Session},
+    report::{BatchReport, Outcome, RunPolicy},
+    rpc,
+    snapshot::SnapshotStatus,
     test_bed::test_case,
-    ui::view_in_less,
+    ui::{Pager, paint_diff_line, view_in_less},
+    websocket::{Session, Step, upgrade},
 };
 
 pub enum Stream {
@@ -42,123 +59,1383 @@ impl Write for Stream {
     }
 }
 
+const CONTINUE_TIMEOUT: Duration = Duration::from_secs(1);
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+impl Stream {
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Http(s) => s.set_read_timeout(timeout),
+            Stream::Https(s) => s.get_ref().set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            Stream::Http(s) => s.set_nodelay(nodelay),
+            Stream::Https(s) => s.get_ref().set_nodelay(nodelay),
+        }
+    }
+}
+
+/// A connection whose reads can be bounded in time.
+pub trait ReadTimeout {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for Stream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        Stream::set_read_timeout(self, timeout)
+    }
+}
+
+impl BodyStream for BodyReader<'_, BufReader<Stream>> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().get_ref().set_read_timeout(timeout)
+    }
+
+    fn take_trailers(&mut self) -> Vec<(String, String)> {
+        BodyReader::take_trailers(self)
+    }
+}
+
+/// Which HTTP version the `protocol` key of `<connect>` allows.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    /// HTTP/2 when the server picks it through ALPN, HTTP/1.1 otherwise and on plain TCP.
+    #[default]
+    Auto,
+    Http1,
+    /// HTTP/2 only, with prior knowledge (h2c) on plain TCP.
+    Http2,
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(Protocol::Auto),
+            "http/1.1" => Ok(Protocol::Http1),
+            "h2" => Ok(Protocol::Http2),
+            _ => Err(format!(
+                "Invalid protocol `{}`, expected auto, http/1.1 or h2",
+                value
+            )),
+        }
+    }
+}
+
 pub struct Connection {
     pub host: String,
     pub port: u16,
     pub is_safe: bool,
+    pub protocol: Protocol,
+    /// Credentials of every query that sets none of its own.
+    pub auth: Option<Auth>,
+    /// The HTTP/1.1 stream, unset while queries go over an HTTP/2 session instead.
     pub reader: Option<BufReader<Stream>>,
+    /// Shared with every connection detached from this one, so parallel queries multiplex over
+    /// a single HTTP/2 connection.
+    pub h2: Arc<Mutex<Option<Arc<H2Session>>>>,
+    /// DNS, connect and TLS durations of the latest handshake, reported with the next request.
+    pub handshake: Timings,
+    /// Shared with every connection detached from this one.
+    pub cookies: Arc<Mutex<CookieJar>>,
+}
+
+impl Connection {
+    /// A copy of the connection settings without an open stream.
+    pub fn detached(&self) -> Connection {
+        Connection {
+            host: self.host.clone(),
+            port: self.port,
+            is_safe: self.is_safe,
+            protocol: self.protocol,
+            auth: self.auth.clone(),
+            reader: None,
+            h2: Arc::clone(&self.h2),
+            handshake: Timings::default(),
+            cookies: Arc::clone(&self.cookies),
+        }
+    }
+
+    /// The HTTP/2 session negotiated for this connection, while it still takes requests.
+    pub fn h2_session(&self) -> Option<Arc<H2Session>> {
+        let slot = self.h2.lock().ok()?;
+        slot.as_ref()
+            .filter(|session| session.is_usable())
+            .map(Arc::clone)
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.reader.is_some() || self.h2_session().is_some()
+    }
+
+    /// The `Host` header and `:authority`, naming the port unless it is the scheme's default.
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        match (self.is_safe, self.port) {
+            (true, 443) | (false, 80) => host,
+            (_, port) => format!("{}:{}", host, port),
+        }
+    }
 }
 
-pub fn connect(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
-    println!(
+/// Opens the connection, announcing it in `out` so parallel queries keep their output together.
+pub fn connect(connection: &mut Connection, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let _ = writeln!(
+        out,
         "Connecting to {} on port {}",
         connection.host, connection.port
     );
-    let address = format!("{}:{}", connection.host, connection.port);
-    let tcp_stream = TcpStream::connect(address)?;
+    open_stream(connection)
+}
+
+/// Same as `connect` without announcing it, for callers that reconnect in a loop.
+pub fn open_stream(connection: &mut Connection) -> Result<(), Box<dyn Error>> {
+    // Held until the session is up, so parallel queries share it instead of racing for their own
+    let slot = Arc::clone(&connection.h2);
+    let mut slot = slot.lock().map_err(|_| "HTTP/2 session is unavailable")?;
+    if connection.protocol != Protocol::Http1
+        && slot.as_ref().is_some_and(|session| session.is_usable())
+    {
+        connection.reader = None;
+        connection.handshake = Timings::default();
+        return Ok(());
+    }
+
+    let (stream, handshake) = open_socket(connection, connection.protocol)?;
+    let is_h2 = match &stream {
+        Stream::Https(tls_stream) => {
+            tls_stream.negotiated_alpn().ok().flatten().as_deref() == Some(b"h2".as_slice())
+        }
+        Stream::Http(_) => connection.protocol == Protocol::Http2,
+    };
+    if connection.protocol == Protocol::Http2 && !is_h2 {
+        return Err(format!("{} did not agree to HTTP/2", connection.host).into());
+    }
+
+    if is_h2 {
+        *slot = Some(Arc::new(H2Session::start(stream)?));
+        connection.reader = None;
+    } else {
+        connection.reader = Some(BufReader::new(stream));
+    }
+    connection.handshake = handshake;
+    Ok(())
+}
+
+/// Opens a socket to the host, offering the protocols `protocol` allows through ALPN.
+fn open_socket(
+    connection: &Connection,
+    protocol: Protocol,
+) -> Result<(Stream, Timings), Box<dyn Error>> {
+    let mut handshake = Timings::default();
+
+    let started = Instant::now();
+    let addresses: Vec<SocketAddr> = (connection.host.as_str(), connection.port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Failed to resolve {}: {}", connection.host, e))?
+        .collect();
+    handshake.dns = started.elapsed();
+
+    let started = Instant::now();
+    let tcp_stream = TcpStream::connect(&addresses[..])?;
+    handshake.connect = started.elapsed();
 
     let stream = if connection.is_safe {
-        let connector = TlsConnector::new()?;
+        let started = Instant::now();
+        let alpn: &[&str] = match protocol {
+            Protocol::Auto => &["h2", "http/1.1"],
+            Protocol::Http1 => &["http/1.1"],
+            Protocol::Http2 => &["h2"],
+        };
+        let connector = TlsConnector::builder().request_alpns(alpn).build()?;
         let tls_stream = connector
             .connect(&connection.host, tcp_stream)
             .map_err(|e| format!("TLS Handshake failed: {}", e))?;
+        handshake.tls = started.elapsed();
         Stream::Https(tls_stream)
     } else {
         Stream::Http(tcp_stream)
     };
 
-    connection.reader = Some(BufReader::new(stream));
-    Ok(())
+    Ok((stream, handshake))
 }
 
 pub fn send_request(connection: &mut Connection, request: &Request) -> Result<Response, String> {
-    let body_bytes = request.body.as_bytes();
-    let content_len = body_bytes.len();
-
-    let request_str = format!(
-    "{method} {url} HTTP/1.1\r\n\
-    Host: {host}\r\n\
-    User-Agent: NetHop/0.0\r\n\
-    Content-Type: {type}\r\n\
-    Content-Length: {len}\r\n\
-    Accept: application/json\r\n\
-    Accept-Encoding: identity\r\n\
-    Connection: keep-alive\r\n\
-    \r\n",
-        method = request.method,
-        url = request.url,
-        host = connection.host,
-        len = content_len,
-        type = request.content_type
-    );
+    exchange(connection, request, BodyTarget::Buffer(&mut io::sink()))
+}
+
+/// Like `send_request`, but the body goes to `sink` as it arrives and the response keeps none of it.
+pub fn send_request_streaming(
+    connection: &mut Connection,
+    request: &Request,
+    sink: &mut dyn Write,
+) -> Result<Response, String> {
+    exchange(connection, request, BodyTarget::Stream(sink))
+}
+
+/// What happens to a response body as it arrives.
+enum BodyTarget<'a> {
+    /// Kept in the response, the events of an event stream being echoed to the writer live.
+    Buffer(&'a mut dyn Write),
+    Stream(&'a mut dyn Write),
+}
+
+impl BodyTarget<'_> {
+    fn reborrow(&mut self) -> BodyTarget<'_> {
+        match self {
+            BodyTarget::Buffer(writer) => BodyTarget::Buffer(&mut **writer),
+            BodyTarget::Stream(writer) => BodyTarget::Stream(&mut **writer),
+        }
+    }
+}
+
+/// The credentials a request goes out with, from the query or else from `<connect>`.
+struct Credentials {
+    auth: Option<Auth>,
+    /// The OAuth2 access token sent, renewed if the server rejects it.
+    token: Option<String>,
+}
+
+impl Credentials {
+    /// Fetches the OAuth2 access token first when there is none cached.
+    fn resolve(connection: &Connection, request: &Request) -> Result<Self, String> {
+        let auth = request.auth.clone().or_else(|| connection.auth.clone());
+        let token = match &auth {
+            Some(Auth::OAuth2(oauth2)) => Some(oauth2.access_token(connection)?),
+            _ => None,
+        };
+        Ok(Credentials { auth, token })
+    }
+
+    /// The `Authorization` header to send up front.
+    fn authorization(&self) -> Option<String> {
+        self.auth
+            .as_ref()
+            .and_then(Auth::authorization)
+            .or_else(|| self.token.as_ref().map(|token| format!("Bearer {}", token)))
+    }
+
+    /// Whether a 401 may be answered with a retry, so its body is not worth keeping.
+    fn answers_unauthorized(&self) -> bool {
+        matches!(self.auth, Some(Auth::Digest { .. } | Auth::OAuth2(_)))
+    }
+
+    /// The `Authorization` header of the one retry after `response` came back 401, if any.
+    fn answer(
+        &self,
+        connection: &Connection,
+        request: &Request,
+        response: &Response,
+    ) -> Result<Option<String>, String> {
+        match (&self.auth, &self.token) {
+            (Some(Auth::Digest { user, password }), _) => {
+                digest_challenge(&response.get_headers("www-authenticate"))
+                    .map(|challenge| {
+                        answer_digest(user, password, challenge, &request.method, &request.url)
+                    })
+                    .transpose()
+            }
+            // Revoked or expired early, a new token gets one more try
+            (Some(Auth::OAuth2(oauth2)), Some(rejected)) => oauth2
+                .renew(rejected, connection)
+                .map(|token| Some(format!("Bearer {}", token))),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn exchange(
+    connection: &mut Connection,
+    request: &Request,
+    mut target: BodyTarget,
+) -> Result<Response, String> {
+    let (body, content_type) = request.encode_body()?;
+    let cookie = connection
+        .cookies
+        .lock()
+        .map_err(|_| "Cookie jar is unavailable")?
+        .header_for(&connection.host, &request.url, connection.is_safe);
+
+    let credentials = Credentials::resolve(connection, request)?;
+    let mut headers = request.headers(&body, &content_type, cookie.as_deref());
+    if let Some(authorization) = credentials.authorization() {
+        headers.push((String::from("Authorization"), authorization));
+    }
+
+    let mut response = send(
+        connection,
+        request,
+        &body,
+        &headers,
+        credentials.answers_unauthorized(),
+        target.reborrow(),
+    )?;
+    if response.status == 401
+        && let Some(authorization) = credentials.answer(connection, request, &response)?
+    {
+        headers.retain(|(name, _)| name != "Authorization");
+        headers.push((String::from("Authorization"), authorization));
+        response = send(connection, request, &body, &headers, false, target)?;
+    }
+
+    if !request.rpc.is_empty() {
+        response.rpc_results = rpc::results(&request.rpc, &response.body);
+    }
+
+    Ok(response)
+}
+
+/// One request and response over whichever protocol the connection speaks, reconnecting first
+/// if the previous response closed it.
+fn send(
+    connection: &mut Connection,
+    request: &Request,
+    body: &Body,
+    headers: &[(String, String)],
+    drop_unauthorized: bool,
+    target: BodyTarget,
+) -> Result<Response, String> {
+    if !connection.is_open() {
+        open_stream(connection).map_err(|err| err.to_string())?;
+    }
+    let response = match connection.h2_session() {
+        Some(session) => exchange_h2(
+            connection,
+            session,
+            request,
+            body,
+            headers,
+            drop_unauthorized,
+            target,
+        )?,
+        None => exchange_h1(
+            connection,
+            request,
+            body,
+            headers,
+            drop_unauthorized,
+            target,
+        )?,
+    };
+
+    let mut jar = connection
+        .cookies
+        .lock()
+        .map_err(|_| "Cookie jar is unavailable")?;
+    for set_cookie in response.get_headers("set-cookie") {
+        jar.store(&connection.host, &request.url, set_cookie);
+    }
+    Ok(response)
+}
+
+fn exchange_h1(
+    connection: &mut Connection,
+    request: &Request,
+    body: &Body,
+    headers: &[(String, String)],
+    drop_unauthorized: bool,
+    target: BodyTarget,
+) -> Result<Response, String> {
+    let head = request.encode_head(&connection.authority(), headers);
 
     let reader = connection.reader.as_mut().ok_or("Not Connected")?;
-    let stream = reader.get_mut();
+    let started = Instant::now();
 
-    stream
-        .write_all(request_str.as_bytes())
+    reader
+        .get_mut()
+        .write_all(head.as_bytes())
         .map_err(|err| format!("Failed to send request: {}", err))?;
 
-    if request.method == "POST" || request.method == "PUT" {
-        if request.body.is_empty() {
-            return Err(format!("Empty body sent to {} request", request.method));
+    let rejection = send_body(reader, request, body)?;
+    reader.get_mut().flush().map_err(|err| err.to_string())?;
+
+    let mut timings = std::mem::take(&mut connection.handshake);
+    // The server may still be waiting for a body we never sent
+    let mut keep_alive = rejection.is_none();
+    let status_line = Cursor::new(rejection.unwrap_or_default());
+    let head = read_head(&mut status_line.chain(&mut *reader), started, &mut timings);
+    let response = head.and_then(|(head, framing)| {
+        let mut response = parse_head(&head)?;
+        let framing = if response_has_body(&request.method, response.status) {
+            framing
+        } else {
+            BodyFraming::empty()
         };
+        let first_byte = Instant::now();
+        let mut body = BodyReader::new(reader, &framing);
+        // Stopping early leaves the rest of the body unread
+        keep_alive &= receive_body(
+            &mut response,
+            &mut body,
+            target,
+            &request.events,
+            drop_unauthorized,
+        )?;
+        // A body running to the end of the connection leaves nothing to reuse
+        keep_alive &= response.keeps_connection() && !framing.is_close_delimited();
+        timings.download = first_byte.elapsed();
+        Ok(response)
+    });
+    if !keep_alive {
+        connection.reader = None;
+    }
+    let mut response = response?;
+    response.timings = timings;
+    Ok(response)
+}
+
+/// Sends the request as a new stream of the session. Cookies, the body and its type go out as
+/// they would over HTTP/1.1, while `chunked` and `expect-continue` have no HTTP/2 equivalent.
+fn exchange_h2(
+    connection: &mut Connection,
+    session: Arc<H2Session>,
+    request: &Request,
+    body: &Body,
+    headers: &[(String, String)],
+    drop_unauthorized: bool,
+    target: BodyTarget,
+) -> Result<Response, String> {
+    let headers: Vec<(String, String)> = headers
+        .iter()
+        .filter(|(name, _)| name != "Transfer-Encoding" && name != "Expect")
+        .cloned()
+        .collect();
+    let scheme = if connection.is_safe { "https" } else { "http" };
+    let authority = connection.authority();
+    let pseudo_headers = [
+        (":method", request.method.as_str()),
+        (":scheme", scheme),
+        (":authority", authority.as_str()),
+        (":path", request.url.as_str()),
+    ];
+
+    let mut timings = std::mem::take(&mut connection.handshake);
+    let started = Instant::now();
+    let id = session.send(pseudo_headers, &headers, body)?;
+    let (status, headers) = session.read_head(id)?;
+    timings.ttfb = started.elapsed();
+
+    let mut response = Response::new("HTTP/2", status, headers);
+    let first_byte = Instant::now();
+    // Dropping the body before its end cancels the stream, the session carries on
+    let mut body = H2Body::new(session, id);
+    receive_body(
+        &mut response,
+        &mut body,
+        target,
+        &request.events,
+        drop_unauthorized,
+    )?;
+    timings.download = first_byte.elapsed();
+    response.timings = timings;
+    Ok(response)
+}
+
+/// Reads the body as `target` asks. Returns whether it was read to its end. With
+/// `drop_unauthorized`, a 401 is only there to be answered with credentials and its body is
+/// dropped.
+fn receive_body(
+    response: &mut Response,
+    body: &mut dyn BodyStream,
+    target: BodyTarget,
+    limit: &EventLimit,
+    drop_unauthorized: bool,
+) -> Result<bool, String> {
+    let finished = match target {
+        _ if drop_unauthorized && response.status == 401 => {
+            copy_body(body, &mut io::sink())?;
+            true
+        }
+        BodyTarget::Stream(sink) => {
+            response.streamed = Some(copy_body(body, sink)?);
+            true
+        }
+        BodyTarget::Buffer(live) if response.is_event_stream() => {
+            let (events, raw, finished) = read_events(body, limit, live)?;
+            response.events = events;
+            response.body = raw;
+            finished
+        }
+        BodyTarget::Buffer(_) => {
+            let mut bytes = Vec::new();
+            copy_body(body, &mut bytes)?;
+            decode_body(response, &String::from_utf8_lossy(&bytes))?;
+            true
+        }
+    };
+    response.trailers = body.take_trailers();
+    Ok(finished)
+}
+
+/// Reads a `text/event-stream` body, echoing each event to `live` as it arrives. Returns the
+/// events, the raw text read and whether the body was read to its end.
+fn read_events(
+    body: &mut dyn BodyStream,
+    limit: &EventLimit,
+    live: &mut dyn Write,
+) -> Result<(Vec<SseEvent>, String, bool), String> {
+    let deadline = limit.timeout.map(|timeout| Instant::now() + timeout);
+    let mut parser = SseParser::default();
+    let mut events = Vec::new();
+    let mut raw = String::new();
+
+    let result = loop {
+        if limit.max_events.is_some_and(|max| events.len() >= max) {
+            break Ok(false);
+        }
+        if let Some(deadline) = deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break Ok(false);
+            }
+            if let Err(err) = body.set_read_timeout(Some(left)) {
+                break Err(err.to_string());
+            }
+        }
+
+        let mut line = String::new();
+        match body.read_line(&mut line) {
+            Ok(0) => break Ok(true),
+            Ok(_) => {}
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                break Ok(false);
+            }
+            Err(err) => break Err(format!("Failed to read stream: {}", err)),
+        }
 
-        stream
-            .write_all(body_bytes)
-            .map_err(|err| format!("Failed to write body: {}", err))?;
+        raw.push_str(&line);
+        if let Some(event) = parser.feed(&line) {
+            if let Err(err) = writeln!(live, "> Event: {}", event) {
+                break Err(err.to_string());
+            }
+            events.push(event);
+        }
+    };
+
+    let _ = body.set_read_timeout(None);
+    result.map(|finished| (events, raw, finished))
+}
+
+/// Sends the body after the head, first waiting for `100 Continue` if the request expects it.
+/// Returns the status line of a final response that came instead, the body being held back.
+fn send_body<S: Read + Write + ReadTimeout>(
+    reader: &mut BufReader<S>,
+    request: &Request,
+    body: &Body,
+) -> Result<Option<Vec<u8>>, String> {
+    if body.is_empty() {
+        return Ok(None);
+    }
+    if request.expect_continue
+        && let Some(rejection) = await_continue(reader)?
+    {
+        return Ok(Some(rejection));
     }
 
-    stream.flush().map_err(|err| err.to_string())?;
+    write_body(reader.get_mut(), body, request.chunked)
+        .map_err(|err| format!("Failed to write body: {}", err))?;
+    Ok(None)
+}
+
+/// Waits for the server to answer `Expect: 100-continue`, skipping other interim responses.
+/// Returns the status line of a final response sent instead, its headers and body left unread.
+/// No answer in time means the body goes out anyway.
+fn await_continue<S: Read + Write + ReadTimeout>(
+    reader: &mut BufReader<S>,
+) -> Result<Option<Vec<u8>>, String> {
+    reader.get_mut().flush().map_err(|err| err.to_string())?;
+
+    loop {
+        reader
+            .get_ref()
+            .set_read_timeout(Some(CONTINUE_TIMEOUT))
+            .map_err(|err| err.to_string())?;
+        let answered = match reader.fill_buf() {
+            Ok(_) => true,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => false,
+            Err(err) => return Err(format!("Failed to read stream: {}", err)),
+        };
+        // The rest of an answer that started to arrive is waited for however it is split up
+        reader
+            .get_ref()
+            .set_read_timeout(None)
+            .map_err(|err| err.to_string())?;
+        if !answered {
+            return Ok(None);
+        }
 
-    let response = read_body(stream).unwrap_or(String::from(""));
-    parse_response(&response)
+        let mut status_line = Vec::new();
+        reader
+            .read_until(b'\n', &mut status_line)
+            .map_err(|err| format!("Failed to read stream: {}", err))?;
+        let status = status_line.get(9..12).unwrap_or_default();
+        if !status.starts_with(b"1") || status == b"101" {
+            return Ok(Some(status_line));
+        }
+
+        // Drop the interim response, headers through the blank line
+        loop {
+            let mut line = Vec::new();
+            let read = reader
+                .read_until(b'\n', &mut line)
+                .map_err(|err| format!("Failed to read stream: {}", err))?;
+            if read == 0 || line == b"\r\n" {
+                break;
+            }
+        }
+        if status == b"100" {
+            return Ok(None);
+        }
+    }
+}
+
+fn write_body(stream: &mut impl Write, body: &Body, chunked: bool) -> io::Result<()> {
+    let mut source = body.reader()?;
+    if !chunked {
+        return io::copy(&mut source, stream).map(|_| ());
+    }
+
+    let mut buffer = vec![0u8; UPLOAD_CHUNK_SIZE];
+    loop {
+        let read = source.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+
+        write!(stream, "{:X}\r\n", read)?;
+        stream.write_all(&buffer[..read])?;
+        stream.write_all(b"\r\n")?;
+    }
+    stream.write_all(b"0\r\n\r\n")
 }
 
 pub fn execute_batch_requests(
     requests: Vec<Request>,
     conn: &mut Connection,
-) -> Result<(), Box<dyn Error>> {
-    for request in requests {
-        execute_request(&request, conn)?;
+    policy: RunPolicy,
+) -> BatchReport {
+    let mut report = BatchReport::default();
+    let mut passed_names: Vec<&str> = Vec::new();
+    let mut stdout = io::stdout();
+
+    for request in &requests {
+        let outcome = match blocked_by(request, &passed_names) {
+            Some(dependency) => skip_query(request, dependency, &mut stdout),
+            None => run_query(request, conn, &mut stdout, true),
+        };
+
+        if matches!(outcome, Outcome::Passed) && !request.name.is_empty() {
+            passed_names.push(&request.name);
+        }
+
+        let should_stop = policy == RunPolicy::FailFast && !matches!(outcome, Outcome::Passed);
+        report.record(request.label(), outcome);
+
+        if should_stop {
+            println!("\n⛔ Stopping early, --fail-fast is set");
+            break;
+        }
     }
 
+    report
+}
+
+/// Runs independent queries on `jobs` worker threads, each owning its own connection.
+/// Output of every query is buffered and printed in script order once it completes.
+pub fn execute_parallel_requests(
+    requests: Vec<Request>,
+    conn: &Connection,
+    policy: RunPolicy,
+    jobs: usize,
+) -> BatchReport {
+    let total = requests.len();
+    let labels: Vec<String> = requests.iter().map(|r| r.label()).collect();
+    let names: Vec<String> = requests.iter().map(|r| r.name.clone()).collect();
+    let dependencies: Vec<Vec<usize>> = requests
+        .iter()
+        .map(|r| {
+            r.depends_on
+                .iter()
+                .filter_map(|dep| requests.iter().position(|other| &other.name == dep))
+                .collect()
+        })
+        .collect();
+
+    let (job_tx, job_rx) = mpsc::channel::<(usize, Request)>();
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    let (result_tx, result_rx) = mpsc::channel::<(usize, Outcome, Vec<u8>)>();
+
+    let mut workers = Vec::with_capacity(jobs);
+    for _ in 0..jobs.min(total) {
+        let job_rx = Arc::clone(&job_rx);
+        let result_tx = result_tx.clone();
+        let mut worker_conn = conn.detached();
+
+        workers.push(thread::spawn(move || {
+            loop {
+                let job = match job_rx.lock() {
+                    Ok(rx) => rx.recv(),
+                    Err(_) => break,
+                };
+                let Ok((index, request)) = job else {
+                    break;
+                };
+
+                let mut output = Vec::new();
+                let outcome = run_query(&request, &mut worker_conn, &mut output, false);
+                if result_tx.send((index, outcome, output)).is_err() {
+                    break;
+                }
+            }
+        }));
+    }
+    drop(result_tx);
+
+    let mut pending: Vec<Option<Request>> = requests.into_iter().map(Some).collect();
+    let mut results: Vec<Option<(Outcome, Vec<u8>)>> = (0..total).map(|_| None).collect();
+    let mut next_to_print = 0;
+    let mut in_flight = 0;
+    let mut stopped = false;
+    let mut stdout = io::stdout();
+
+    loop {
+        // Skipping a query can unblock the decision for its own dependents, so repeat until settled
+        let mut progressed = !stopped;
+        while progressed {
+            progressed = false;
+            for index in 0..total {
+                if pending[index].is_none() {
+                    continue;
+                }
+
+                let failed_dependency = dependencies[index].iter().find(|&&dep| {
+                    matches!(&results[dep], Some((outcome, _)) if !matches!(outcome, Outcome::Passed))
+                });
+                if let Some(&dep) = failed_dependency {
+                    let mut output = Vec::new();
+                    if let Some(request) = pending[index].take() {
+                        let outcome = skip_query(&request, &names[dep], &mut output);
+                        results[index] = Some((outcome, output));
+                        progressed = true;
+                    }
+                    continue;
+                }
+
+                let is_ready = dependencies[index]
+                    .iter()
+                    .all(|&dep| results[dep].is_some());
+                if is_ready
+                    && let Some(request) = pending[index].take()
+                    && job_tx.send((index, request)).is_ok()
+                {
+                    in_flight += 1;
+                }
+            }
+        }
+
+        while next_to_print < total {
+            match &results[next_to_print] {
+                Some((_, output)) => {
+                    let _ = stdout.write_all(output);
+                    next_to_print += 1;
+                }
+                None => break,
+            }
+        }
+
+        if in_flight == 0 {
+            break;
+        }
+
+        let Ok((index, outcome, output)) = result_rx.recv() else {
+            break;
+        };
+        in_flight -= 1;
+
+        if policy == RunPolicy::FailFast && !matches!(outcome, Outcome::Passed) && !stopped {
+            stopped = true;
+            println!("\n⛔ Stopping early, --fail-fast is set");
+        }
+        results[index] = Some((outcome, output));
+    }
+
+    drop(job_tx);
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    let mut report = BatchReport::default();
+    for (index, result) in results.into_iter().enumerate() {
+        if let Some((outcome, output)) = result {
+            if index >= next_to_print {
+                let _ = stdout.write_all(&output);
+            }
+            report.record(labels[index].clone(), outcome);
+        }
+    }
+
+    report
+}
+
+/// Returns the name of the first dependency of `request` that has not passed yet.
+fn blocked_by<'a>(request: &'a Request, passed_names: &[&str]) -> Option<&'a str> {
+    request
+        .depends_on
+        .iter()
+        .map(|dep| dep.as_str())
+        .find(|dep| !passed_names.contains(dep))
+}
+
+fn skip_query(request: &Request, dependency: &str, out: &mut dyn Write) -> Outcome {
+    let reason = format!("Skipped, dependency {} did not pass", dependency);
+    let _ = writeln!(out, "\n{}\n⏭️  {}", request.label(), reason);
+    Outcome::Errored(reason)
+}
+
+fn run_query(
+    request: &Request,
+    conn: &mut Connection,
+    out: &mut dyn Write,
+    use_pager: bool,
+) -> Outcome {
+    if !conn.is_open()
+        && let Err(err) = connect(conn, out)
+    {
+        let _ = writeln!(out, "\n{}\n💥 {}", request.label(), err);
+        return Outcome::Errored(err.to_string());
+    }
+
+    match execute_request(request, conn, out, use_pager) {
+        Ok(true) => Outcome::Passed,
+        Ok(false) => Outcome::Failed,
+        Err(err) => {
+            let _ = writeln!(out, "💥 {}", err);
+            // The stream may be left half read, start the next query on a fresh socket
+            conn.reader = None;
+            Outcome::Errored(err.to_string())
+        }
+    }
+}
+
+fn write_failure(out: &mut dyn Write, details: &[String]) -> io::Result<()> {
+    writeln!(out, " -> Failed")?;
+    for detail in details {
+        writeln!(out, "    {}", paint_diff_line(detail))?;
+    }
     Ok(())
 }
 
-fn execute_request(request: &Request, conn: &mut Connection) -> Result<(), Box<dyn Error>> {
-    println!("\n[{}: {}]", request.method, request.url);
-    let response = send_request(conn, request)?;
+/// Prints the outcome of one case, returning whether it passed.
+fn write_case(out: &mut dyn Write, result: Result<(), Vec<String>>) -> io::Result<bool> {
+    match result {
+        Ok(()) => {
+            writeln!(out, " -> Passed")?;
+            Ok(true)
+        }
+        Err(details) => {
+            write_failure(out, &details)?;
+            Ok(false)
+        }
+    }
+}
+
+fn write_report(
+    out: &mut dyn Write,
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+) -> io::Result<()> {
+    writeln!(out, "\nReport:")?;
+    write!(
+        out,
+        "Ran {} test(s), {} test(s) passed, {} test(s) failed",
+        passed + failed,
+        passed,
+        failed
+    )?;
+    if skipped > 0 {
+        write!(out, ", {} test(s) skipped", skipped)?;
+    }
+    writeln!(out)
+}
+
+fn execute_request(
+    request: &Request,
+    conn: &mut Connection,
+    out: &mut dyn Write,
+    use_pager: bool,
+) -> Result<bool, Box<dyn Error>> {
+    writeln!(out, "\n{}", request.label())?;
+    if !request.websocket.is_empty() {
+        return run_websocket(request, conn, out);
+    }
+
+    let response = match &request.stream {
+        Some(target) => stream_response(request, target, conn, use_pager)?,
+        None => exchange(conn, request, BodyTarget::Buffer(&mut *out))?,
+    };
+    writeln!(out, "> Timing: {}", response.timings)?;
+    writeln!(out, "> Protocol: {}", response.version)?;
 
-    if !request.test_cases.is_empty() {
+    if !request.test_cases.is_empty()
+        || request.snapshot.is_some()
+        || request.graphql.is_some()
+        || !request.rpc.is_empty()
+    {
         let mut passed = 0;
         let mut failed = 0;
-        println!("Running {} test(s)\n", request.test_cases.len());
+        writeln!(
+            out,
+            "Running {} test(s)\n",
+            request.test_cases.len()
+                + usize::from(request.snapshot.is_some())
+                + usize::from(request.graphql.is_some())
+                + usize::from(!request.rpc.is_empty())
+        )?;
+        if !request.rpc.is_empty() {
+            write!(out, "Case: no rpc errors")?;
+            if write_case(out, rpc::check_errors(&request.rpc, &response.body))? {
+                passed += 1;
+            } else {
+                failed += 1;
+            }
+        }
+        if request.graphql.is_some() {
+            write!(out, "Case: no graphql errors")?;
+            if write_case(out, check_errors(&response))? {
+                passed += 1;
+            } else {
+                failed += 1;
+            }
+        }
         for case in &request.test_cases {
-            if test_case(&response, case) {
-                println!(" -> Passed");
+            write!(out, "Case: {}", case)?;
+            if write_case(out, test_case(&response, case))? {
                 passed += 1;
             } else {
-                println!(" -> Failed");
                 failed += 1;
             }
         }
 
-        println!("\nReport:");
-        println!(
-            "Ran {} test(s), {} test(s) passed, {} test(s) failed",
-            passed + failed,
-            passed,
-            failed
-        );
+        if let Some(snapshot) = &request.snapshot {
+            write!(out, "Case: snapshot {}", snapshot.name)?;
+            match snapshot.check(&response) {
+                Ok(status) => {
+                    match status {
+                        SnapshotStatus::Matched => writeln!(out, " -> Passed")?,
+                        SnapshotStatus::Written => writeln!(out, " -> Passed (written)")?,
+                        SnapshotStatus::Updated => writeln!(out, " -> Passed (updated)")?,
+                    }
+                    passed += 1;
+                }
+                Err(details) => {
+                    write_failure(out, &details)?;
+                    failed += 1;
+                }
+            }
+        }
+
+        write_report(out, passed, failed, 0)?;
+        return Ok(failed == 0);
+    }
+
+    writeln!(out, "> Status: {}", response.status)?;
+    writeln!(
+        out,
+        "> Date: {}",
+        response.get_header("Date").unwrap_or("--")
+    )?;
+    if response.is_event_stream() && response.streamed.is_none() {
+        writeln!(out, "> Events: {} received", response.events.len())?;
+        return Ok(true);
+    }
+
+    if let Some(bytes) = response.streamed {
+        let destination = match &request.stream {
+            Some(StreamTarget::File(path)) => path.display().to_string(),
+            _ if use_pager => String::from("pager"),
+            _ => String::from("nowhere, no pager available"),
+        };
+        writeln!(out, "> Body: {} bytes streamed to {}", bytes, destination)?;
+        return Ok(true);
+    }
+
+    let content = format!(
+        "[{}: {}{}]\n\n{}",
+        request.method, conn.host, request.url, response.body
+    );
+    if use_pager {
+        view_in_less(&content)?;
     } else {
-        println!("> Status: {}", response.status);
-        println!("> Date: {}", response.get_header("Date").unwrap_or("--"));
-        view_in_less(&format!(
-            "[{}: {}{}]\n\n{}",
-            request.method, conn.host, request.url, response.body
-        ))?;
+        writeln!(out, "{}", content)?;
     }
 
-    Ok(())
+    Ok(true)
+}
+
+/// Sends a query whose body goes straight to its stream target instead of memory.
+fn stream_response(
+    request: &Request,
+    target: &StreamTarget,
+    conn: &mut Connection,
+    use_pager: bool,
+) -> Result<Response, Box<dyn Error>> {
+    match target {
+        StreamTarget::File(path) => {
+            let mut file = File::create(path)
+                .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+            Ok(send_request_streaming(conn, request, &mut file)?)
+        }
+        StreamTarget::Pager if use_pager => {
+            let mut pager = Pager::open()?;
+            write!(
+                pager,
+                "[{}: {}{}]\n\n",
+                request.method, conn.host, request.url
+            )?;
+            let response = send_request_streaming(conn, request, &mut pager);
+            pager.finish()?;
+            Ok(response?)
+        }
+        // Parallel runs have no pager, keep the query cheap by dropping the body
+        StreamTarget::Pager => Ok(send_request_streaming(conn, request, &mut io::sink())?),
+    }
+}
+
+/// Upgrades the connection, checks the handshake against the query's assertions and plays its
+/// `<websocket>` steps. The socket is spent afterwards, the next query opens a new one.
+fn run_websocket(
+    request: &Request,
+    conn: &mut Connection,
+    out: &mut dyn Write,
+) -> Result<bool, Box<dyn Error>> {
+    let cookie = conn
+        .cookies
+        .lock()
+        .map_err(|_| "Cookie jar is unavailable")?
+        .header_for(&conn.host, &request.url, conn.is_safe);
+    let credentials = Credentials::resolve(conn, request)?;
+    let mut headers = Vec::new();
+    if let Some(cookie) = cookie {
+        headers.push((String::from("Cookie"), cookie));
+    }
+    if let Some(authorization) = credentials.authorization() {
+        headers.push((String::from("Authorization"), authorization));
+    }
+
+    // The upgrade only exists in HTTP/1.1, a socket of its own leaves the HTTP/2 session be
+    let (mut reader, mut timings) = match conn.reader.take() {
+        Some(reader) => (reader, std::mem::take(&mut conn.handshake)),
+        None if conn.h2_session().is_some() => {
+            let (stream, timings) = open_socket(conn, Protocol::Http1)?;
+            (BufReader::new(stream), timings)
+        }
+        None => return Err("Not Connected".into()),
+    };
+    let mut response = upgrade(
+        &mut reader,
+        &conn.authority(),
+        &request.url,
+        &headers,
+        &mut timings,
+    )?;
+    if response.status == 401
+        && let Some(authorization) = credentials.answer(conn, request, &response)?
+    {
+        // The refused upgrade is left unread, the retry goes out on a fresh socket
+        let (stream, handshake) = open_socket(conn, Protocol::Http1)?;
+        reader = BufReader::new(stream);
+        timings = handshake;
+        headers.retain(|(name, _)| name != "Authorization");
+        headers.push((String::from("Authorization"), authorization));
+        response = upgrade(
+            &mut reader,
+            &conn.authority(),
+            &request.url,
+            &headers,
+            &mut timings,
+        )?;
+    }
+    if response.status != 101 {
+        return Err(format!(
+            "Server refused the WebSocket upgrade with status {}",
+            response.status
+        )
+        .into());
+    }
+    writeln!(out, "> Timing: {}", response.timings)?;
+
+    let mut jar = conn
+        .cookies
+        .lock()
+        .map_err(|_| "Cookie jar is unavailable")?;
+    for set_cookie in response.get_headers("set-cookie") {
+        jar.store(&conn.host, &request.url, set_cookie);
+    }
+    drop(jar);
+
+    let sends = request
+        .websocket
+        .iter()
+        .filter(|step| matches!(step, Step::Send(_)))
+        .count();
+    writeln!(
+        out,
+        "Running {} test(s)\n",
+        request.test_cases.len() + request.websocket.len() - sends
+    )?;
+
+    let mut passed = 0;
+    let mut failed = 0;
+    for case in &request.test_cases {
+        write!(out, "Case: {}", case)?;
+        if write_case(out, test_case(&response, case))? {
+            passed += 1;
+        } else {
+            failed += 1;
+        }
+    }
+
+    let mut session = Session::new(&mut reader);
+    let mut aborted = false;
+    let mut skipped = 0;
+    for step in &request.websocket {
+        write!(out, "Step: {}", step)?;
+        if aborted {
+            // The session is out of step with the script after a failure
+            writeln!(out, " -> Skipped")?;
+            if !matches!(step, Step::Send(_)) {
+                skipped += 1;
+            }
+            continue;
+        }
+
+        let result = session.run(step);
+        if matches!(step, Step::Send(_)) && result.is_ok() {
+            writeln!(out, " -> Sent")?;
+        } else if write_case(out, result)? {
+            passed += 1;
+        } else {
+            failed += 1;
+            aborted = true;
+        }
+    }
+    session.finish();
+
+    write_report(out, passed, failed, skipped)?;
+    Ok(failed == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Read(String),
+        Wrote(String),
+    }
+
+    /// A server that answers reads from a script, `None` being a read that times out.
+    #[derive(Default)]
+    struct Peer {
+        replies: VecDeque<Option<&'static str>>,
+        events: Vec<Event>,
+        timeouts: RefCell<Vec<Option<Duration>>>,
+    }
+
+    impl Read for Peer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.replies.pop_front() {
+                Some(Some(reply)) => {
+                    buf[..reply.len()].copy_from_slice(reply.as_bytes());
+                    self.events.push(Event::Read(reply.to_string()));
+                    Ok(reply.len())
+                }
+                Some(None) => Err(io::Error::new(ErrorKind::TimedOut, "silent")),
+                None => Ok(0),
+            }
+        }
+    }
+
+    impl Write for Peer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let text = String::from_utf8_lossy(buf);
+            match self.events.last_mut() {
+                Some(Event::Wrote(written)) => written.push_str(&text),
+                _ => self.events.push(Event::Wrote(text.into_owned())),
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ReadTimeout for Peer {
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.timeouts.borrow_mut().push(timeout);
+            Ok(())
+        }
+    }
+
+    /// Sends a `data` body to a peer answering with `replies`.
+    fn send(
+        replies: &[Option<&'static str>],
+        expect_continue: bool,
+    ) -> (Option<String>, Vec<Event>, Vec<Option<Duration>>) {
+        let mut reader = BufReader::new(Peer {
+            replies: replies.iter().copied().collect(),
+            ..Default::default()
+        });
+        let request = Request {
+            expect_continue,
+            ..Default::default()
+        };
+        let rejection = send_body(&mut reader, &request, &Body::Bytes(b"data".to_vec())).unwrap();
+        let peer = reader.into_inner();
+        let timeouts = peer.timeouts.take();
+        (
+            rejection.map(|line| String::from_utf8(line).unwrap()),
+            peer.events,
+            timeouts,
+        )
+    }
+
+    fn wrote(text: &str) -> Event {
+        Event::Wrote(text.to_string())
+    }
+
+    #[test]
+    fn frames_chunked_uploads() {
+        let mut written = Vec::new();
+        write_body(&mut written, &Body::Bytes(b"hello world".to_vec()), true).unwrap();
+        assert_eq!(written, b"B\r\nhello world\r\n0\r\n\r\n");
+
+        let mut written = Vec::new();
+        write_body(&mut written, &Body::Generated { len: 70_000 }, true).unwrap();
+        let mut expected = b"10000\r\n".to_vec();
+        expected.extend([b'0'; UPLOAD_CHUNK_SIZE]);
+        expected.extend(b"\r\n1170\r\n");
+        expected.extend([b'0'; 70_000 - UPLOAD_CHUNK_SIZE]);
+        expected.extend(b"\r\n0\r\n\r\n");
+        assert_eq!(written, expected);
+
+        let mut written = Vec::new();
+        write_body(&mut written, &Body::Bytes(b"hello".to_vec()), false).unwrap();
+        assert_eq!(written, b"hello");
+    }
+
+    #[test]
+    fn sends_the_body_right_away_without_expect() {
+        assert_eq!(send(&[], false), (None, vec![wrote("data")], Vec::new()));
+    }
+
+    #[test]
+    fn holds_the_body_until_continue() {
+        let (rejection, events, timeouts) = send(&[Some("HTTP/1.1 100 Continue\r\n\r\n")], true);
+        assert_eq!(rejection, None);
+        assert_eq!(
+            events,
+            [
+                Event::Read(String::from("HTTP/1.1 100 Continue\r\n\r\n")),
+                wrote("data")
+            ]
+        );
+        assert_eq!(timeouts, [Some(CONTINUE_TIMEOUT), None]);
+    }
+
+    #[test]
+    fn reads_a_split_continue_whole() {
+        let (rejection, events, _) = send(
+            &[Some("HTTP/1.1 1"), Some("00 Cont"), Some("inue\r\n\r\n")],
+            true,
+        );
+        assert_eq!(rejection, None);
+        assert_eq!(events.last(), Some(&wrote("data")));
+        assert_eq!(events.len(), 4);
+    }
+
+    #[test]
+    fn skips_other_interim_responses() {
+        let (rejection, events, timeouts) = send(
+            &[
+                Some("HTTP/1.1 102 Processing\r\n\r\n"),
+                Some("HTTP/1.1 100 Continue\r\n\r\n"),
+            ],
+            true,
+        );
+        assert_eq!(rejection, None);
+        assert_eq!(events.last(), Some(&wrote("data")));
+        assert_eq!(timeouts.len(), 4);
+    }
+
+    #[test]
+    fn sends_the_body_to_a_silent_server() {
+        let (rejection, events, _) = send(&[None], true);
+        assert_eq!(rejection, None);
+        assert_eq!(events, [wrote("data")]);
+    }
+
+    #[test]
+    fn keeps_the_body_from_an_early_final_response() {
+        let (rejection, events, _) = send(
+            &[Some(
+                "HTTP/1.1 417 Expectation Failed\r\nContent-Length: 0\r\n\r\n",
+            )],
+            true,
+        );
+        assert_eq!(
+            rejection.as_deref(),
+            Some("HTTP/1.1 417 Expectation Failed\r\n")
+        );
+        assert!(!events.iter().any(|event| matches!(event, Event::Wrote(_))));
+    }
+
+    fn connection(host: &str, port: u16, is_safe: bool) -> Connection {
+        Connection {
+            host: host.to_string(),
+            port,
+            is_safe,
+            protocol: Protocol::default(),
+            auth: None,
+            reader: None,
+            h2: Arc::default(),
+            handshake: Timings::default(),
+            cookies: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn authority_names_non_default_ports() {
+        assert_eq!(
+            connection("example.com", 443, true).authority(),
+            "example.com"
+        );
+        assert_eq!(
+            connection("example.com", 80, false).authority(),
+            "example.com"
+        );
+        assert_eq!(
+            connection("example.com", 8443, true).authority(),
+            "example.com:8443"
+        );
+        assert_eq!(
+            connection("example.com", 443, false).authority(),
+            "example.com:443"
+        );
+        assert_eq!(connection("::1", 8080, false).authority(), "[::1]:8080");
+        assert_eq!(connection("::1", 443, true).authority(), "[::1]");
+    }
 }
@@ -0,0 +1,497 @@
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+use crate::{
+    auth::Auth,
+    http::{Request, Timings},
+    network::{Connection, Protocol, send_request},
+};
+
+/// Most a token lifetime is cut short by, so a token is not sent just as it runs out.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+/// How the client proves who it is to the token endpoint.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum ClientAuth {
+    /// `client_id` and `client_secret` as Basic credentials, which every server must accept.
+    #[default]
+    Basic,
+    /// `client_id` and `client_secret` as form fields.
+    Body,
+}
+
+impl FromStr for ClientAuth {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "basic" => Ok(ClientAuth::Basic),
+            "body" => Ok(ClientAuth::Body),
+            _ => Err(format!(
+                "Invalid client-auth `{}`, expected basic or body",
+                value
+            )),
+        }
+    }
+}
+
+/// Settings of an `<oauth2>` block and the access token they last got, shared by every query.
+#[derive(Default)]
+pub struct OAuth2 {
+    /// Absolute, or a path on the host of `<connect>`.
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scopes: Vec<String>,
+    /// Uses the refresh token grant instead of client credentials.
+    pub refresh_token: Option<String>,
+    pub client_auth: ClientAuth,
+    token: Mutex<Option<Token>>,
+}
+
+struct Token {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_at: Option<Instant>,
+}
+
+impl Token {
+    fn is_fresh(&self) -> bool {
+        self.expires_at
+            .is_none_or(|expires_at| Instant::now() < expires_at)
+    }
+}
+
+impl OAuth2 {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.token_url.is_empty() {
+            return Err("<oauth2> needs a token-url".to_string());
+        }
+        if self.client_id.is_empty() {
+            return Err("<oauth2> needs a client-id".to_string());
+        }
+        Ok(())
+    }
+
+    /// The cached access token, fetched first if there is none or it is about to expire.
+    /// Parallel queries wait for a single fetch.
+    pub fn access_token(&self, connection: &Connection) -> Result<String, String> {
+        let mut cached = self
+            .token
+            .lock()
+            .map_err(|_| "OAuth2 token is unavailable")?;
+        if let Some(token) = cached.as_ref().filter(|token| token.is_fresh()) {
+            return Ok(token.access_token.clone());
+        }
+
+        let token = self.fetch(cached.as_ref(), connection)?;
+        let access_token = token.access_token.clone();
+        *cached = Some(token);
+        Ok(access_token)
+    }
+
+    /// A new access token in place of `rejected`, which the server answered with a 401. Only
+    /// one of several queries rejected together fetches it.
+    pub fn renew(&self, rejected: &str, connection: &Connection) -> Result<String, String> {
+        let mut cached = self
+            .token
+            .lock()
+            .map_err(|_| "OAuth2 token is unavailable")?;
+        if let Some(token) = cached
+            .as_ref()
+            .filter(|token| token.access_token != rejected && token.is_fresh())
+        {
+            return Ok(token.access_token.clone());
+        }
+
+        let token = self.fetch(cached.as_ref(), connection)?;
+        let access_token = token.access_token.clone();
+        *cached = Some(token);
+        Ok(access_token)
+    }
+
+    /// Refreshes `previous` when it came with a refresh token, falling back to client
+    /// credentials if the server no longer takes it.
+    fn fetch(&self, previous: Option<&Token>, connection: &Connection) -> Result<Token, String> {
+        let issued = previous.and_then(|token| token.refresh_token.as_deref());
+        match (issued, &self.refresh_token) {
+            (Some(refresh_token), None) => self
+                .request_token(Some(refresh_token), connection)
+                .or_else(|_| self.request_token(None, connection)),
+            (issued, configured) => {
+                self.request_token(issued.or(configured.as_deref()), connection)
+            }
+        }
+    }
+
+    fn request_token(
+        &self,
+        refresh_token: Option<&str>,
+        connection: &Connection,
+    ) -> Result<Token, String> {
+        let (mut token_connection, path) = self.token_endpoint(connection)?;
+
+        let mut form = match refresh_token {
+            Some(refresh_token) => vec![
+                (String::from("grant_type"), String::from("refresh_token")),
+                (String::from("refresh_token"), refresh_token.to_string()),
+            ],
+            None => vec![(
+                String::from("grant_type"),
+                String::from("client_credentials"),
+            )],
+        };
+        if !self.scopes.is_empty() {
+            form.push((String::from("scope"), self.scopes.join(" ")));
+        }
+        let auth = match self.client_auth {
+            ClientAuth::Basic => Auth::Basic {
+                user: self.client_id.clone(),
+                password: self.client_secret.clone(),
+            },
+            ClientAuth::Body => {
+                form.push((String::from("client_id"), self.client_id.clone()));
+                if !self.client_secret.is_empty() {
+                    form.push((String::from("client_secret"), self.client_secret.clone()));
+                }
+                Auth::None
+            }
+        };
+
+        let request = Request {
+            method: String::from("POST"),
+            url: path,
+            form,
+            auth: Some(auth),
+            ..Default::default()
+        };
+        let response = send_request(&mut token_connection, &request)
+            .map_err(|e| format!("Token request to {} failed: {}", self.token_url, e))?;
+        let json: Value = serde_json::from_str(&response.body).map_err(|_| {
+            format!(
+                "Token request to {} failed with status {}",
+                self.token_url, response.status
+            )
+        })?;
+
+        if response.status != 200 {
+            let error = json["error"].as_str().unwrap_or("unknown error");
+            return Err(match json["error_description"].as_str() {
+                Some(description) => format!(
+                    "Token request to {} failed with status {}: {} ({})",
+                    self.token_url, response.status, error, description
+                ),
+                None => format!(
+                    "Token request to {} failed with status {}: {}",
+                    self.token_url, response.status, error
+                ),
+            });
+        }
+
+        let access_token = json["access_token"]
+            .as_str()
+            .ok_or(format!("No access_token from {}", self.token_url))?;
+        if let Some(token_type) = json["token_type"].as_str()
+            && !token_type.eq_ignore_ascii_case("bearer")
+        {
+            return Err(format!(
+                "Unsupported token type {} from {}",
+                token_type, self.token_url
+            ));
+        }
+        // Some servers send the lifetime as a string
+        let expires_in = json["expires_in"]
+            .as_u64()
+            .or_else(|| json["expires_in"].as_str()?.parse().ok());
+
+        Ok(Token {
+            access_token: access_token.to_string(),
+            // Kept when the server does not rotate it
+            refresh_token: json["refresh_token"]
+                .as_str()
+                .or(refresh_token)
+                .map(String::from),
+            expires_at: expires_in.map(|seconds| Instant::now() + usable_lifetime(seconds)),
+        })
+    }
+
+    /// A connection to the token endpoint and the path to post to.
+    fn token_endpoint(&self, connection: &Connection) -> Result<(Connection, String), String> {
+        let (is_safe, rest) = match self.token_url.split_once("://") {
+            Some(("https", rest)) => (true, rest),
+            Some(("http", rest)) => (false, rest),
+            Some((scheme, _)) => return Err(format!("Unsupported token-url scheme {}", scheme)),
+            None => return Ok((connection.detached(), self.token_url.clone())),
+        };
+
+        let (authority, path) = rest
+            .find('/')
+            .map(|slash| (&rest[..slash], rest[slash..].to_string()))
+            .unwrap_or((rest, String::from("/")));
+        // An IPv6 address is bracketed, its colons being no port
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) if port.starts_with(':') => (host, Some(&port[1..])),
+                _ => return Err(format!("Invalid host in token-url {}", self.token_url)),
+            },
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("Invalid port in token-url {}", self.token_url))?,
+            None if is_safe => 443,
+            None => 80,
+        };
+
+        let token_connection = Connection {
+            host: host.to_string(),
+            port,
+            is_safe,
+            protocol: Protocol::default(),
+            auth: None,
+            reader: None,
+            h2: Arc::default(),
+            handshake: Timings::default(),
+            cookies: Arc::default(),
+        };
+        Ok((token_connection, path))
+    }
+}
+
+/// How long a token that lives `seconds` is sent for.
+fn usable_lifetime(seconds: u64) -> Duration {
+    let lifetime = Duration::from_secs(seconds);
+    lifetime.saturating_sub((lifetime / 10).min(EXPIRY_MARGIN))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread::{self, JoinHandle},
+    };
+
+    use super::*;
+
+    /// Answers one request per connection with each of `responses` in turn, handing back the
+    /// requests it got.
+    fn serve(responses: Vec<String>) -> (u16, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|response| {
+                    let (stream, _) = listener.accept().unwrap();
+                    stream
+                        .set_read_timeout(Some(Duration::from_secs(5)))
+                        .unwrap();
+                    let mut reader = BufReader::new(stream);
+                    let mut request = String::new();
+                    let mut length = 0;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if let Some(value) =
+                            line.to_ascii_lowercase().strip_prefix("content-length:")
+                        {
+                            length = value.trim().parse().unwrap();
+                        }
+                        request.push_str(&line);
+                        if line == "\r\n" {
+                            break;
+                        }
+                    }
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+                    request.push_str(&String::from_utf8(body).unwrap());
+                    reader.get_mut().write_all(response.as_bytes()).unwrap();
+                    request
+                })
+                .collect()
+        });
+        (port, server)
+    }
+
+    fn json(status: u16, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {} Status\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+            Connection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    }
+
+    fn connection(port: u16) -> Connection {
+        Connection {
+            host: String::from("127.0.0.1"),
+            port,
+            is_safe: false,
+            protocol: Protocol::Http1,
+            auth: None,
+            reader: None,
+            h2: Arc::default(),
+            handshake: Timings::default(),
+            cookies: Arc::default(),
+        }
+    }
+
+    fn oauth2() -> OAuth2 {
+        OAuth2 {
+            token_url: String::from("/token"),
+            client_id: String::from("app"),
+            client_secret: String::from("s3cret"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fetches_and_caches_client_credentials() {
+        let (port, server) = serve(vec![json(
+            200,
+            r#"{"access_token":"at-1","token_type":"bearer","expires_in":"3600"}"#,
+        )]);
+        let oauth2 = OAuth2 {
+            scopes: vec![String::from("read"), String::from("write")],
+            ..oauth2()
+        };
+        let connection = connection(port);
+
+        assert_eq!(oauth2.access_token(&connection), Ok(String::from("at-1")));
+        assert_eq!(oauth2.access_token(&connection), Ok(String::from("at-1")));
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("POST /token HTTP/1.1\r\n"));
+        // app:s3cret
+        assert!(requests[0].contains("Authorization: Basic YXBwOnMzY3JldA==\r\n"));
+        assert!(requests[0].ends_with("grant_type=client_credentials&scope=read+write"));
+    }
+
+    #[test]
+    fn rotates_refresh_tokens_and_falls_back_to_client_credentials() {
+        let (port, server) = serve(vec![
+            json(
+                200,
+                r#"{"access_token":"at-1","expires_in":0,"refresh_token":"rt-1"}"#,
+            ),
+            json(
+                200,
+                r#"{"access_token":"at-2","expires_in":0,"refresh_token":"rt-2"}"#,
+            ),
+            json(400, r#"{"error":"invalid_grant"}"#),
+            json(200, r#"{"access_token":"at-3"}"#),
+        ]);
+        let oauth2 = OAuth2 {
+            client_auth: ClientAuth::Body,
+            ..oauth2()
+        };
+        let connection = connection(port);
+
+        assert_eq!(oauth2.access_token(&connection), Ok(String::from("at-1")));
+        assert_eq!(oauth2.access_token(&connection), Ok(String::from("at-2")));
+        assert_eq!(oauth2.access_token(&connection), Ok(String::from("at-3")));
+        let requests = server.join().unwrap();
+        assert!(!requests[0].contains("Authorization"));
+        assert!(
+            requests[0]
+                .ends_with("grant_type=client_credentials&client_id=app&client_secret=s3cret")
+        );
+        assert!(requests[1].contains("grant_type=refresh_token&refresh_token=rt-1&"));
+        assert!(requests[2].contains("grant_type=refresh_token&refresh_token=rt-2&"));
+        assert!(requests[3].contains("grant_type=client_credentials&"));
+    }
+
+    #[test]
+    fn cuts_the_lifetime_short() {
+        assert_eq!(usable_lifetime(3600), Duration::from_secs(3570));
+        assert_eq!(usable_lifetime(60), Duration::from_secs(54));
+        assert_eq!(usable_lifetime(0), Duration::ZERO);
+    }
+
+    #[test]
+    fn reports_token_errors() {
+        let (port, server) = serve(vec![json(
+            401,
+            r#"{"error":"invalid_client","error_description":"bad secret"}"#,
+        )]);
+
+        assert_eq!(
+            oauth2().access_token(&connection(port)),
+            Err(String::from(
+                "Token request to /token failed with status 401: invalid_client (bad secret)"
+            ))
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn retries_a_rejected_token_once() {
+        let (port, server) = serve(vec![
+            json(200, r#"{"access_token":"at-1","expires_in":3600}"#),
+            json(401, r#"{"error":"invalid_token"}"#),
+            json(200, r#"{"access_token":"at-2","expires_in":3600}"#),
+            json(200, r#"{"ok":true}"#),
+        ]);
+        let request = Request {
+            method: String::from("GET"),
+            url: String::from("/api"),
+            auth: Some(Auth::OAuth2(Arc::new(oauth2()))),
+            ..Default::default()
+        };
+
+        let response = send_request(&mut connection(port), &request).unwrap();
+        assert_eq!(response.status, 200);
+        let requests = server.join().unwrap();
+        assert!(requests[1].contains("Authorization: Bearer at-1\r\n"));
+        assert!(requests[2].starts_with("POST /token "));
+        assert!(requests[3].contains("Authorization: Bearer at-2\r\n"));
+    }
+
+    #[test]
+    fn splits_token_urls() {
+        let endpoint = |token_url: &str| {
+            let oauth2 = OAuth2 {
+                token_url: token_url.to_string(),
+                ..oauth2()
+            };
+            oauth2
+                .token_endpoint(&connection(80))
+                .map(|(connection, path)| {
+                    (connection.host, connection.port, connection.is_safe, path)
+                })
+        };
+
+        assert_eq!(
+            endpoint("http://[::1]:8080/token"),
+            Ok((String::from("::1"), 8080, false, String::from("/token")))
+        );
+        assert_eq!(
+            endpoint("https://[::1]"),
+            Ok((String::from("::1"), 443, true, String::from("/")))
+        );
+        assert_eq!(
+            endpoint("https://auth.example.com:8443/oauth/token?x=1"),
+            Ok((
+                String::from("auth.example.com"),
+                8443,
+                true,
+                String::from("/oauth/token?x=1")
+            ))
+        );
+        assert!(endpoint("http://[::1/token").is_err());
+        assert!(endpoint("http://[::1]8080/token").is_err());
+        assert!(endpoint("ftp://example.com/token").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use std::io::{self, Write};
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum RunPolicy {
+    FailFast,
+    #[default]
+    Continue,
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Errored(String),
+}
+
+#[derive(Debug)]
+pub struct QueryReport {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+#[derive(Default, Debug)]
+pub struct BatchReport {
+    pub queries: Vec<QueryReport>,
+}
+
+impl BatchReport {
+    pub fn record(&mut self, name: String, outcome: Outcome) {
+        self.queries.push(QueryReport { name, outcome });
+    }
+
+    pub fn passed(&self) -> impl Iterator<Item = &QueryReport> {
+        self.queries
+            .iter()
+            .filter(|q| matches!(q.outcome, Outcome::Passed))
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &QueryReport> {
+        self.queries
+            .iter()
+            .filter(|q| matches!(q.outcome, Outcome::Failed))
+    }
+
+    pub fn errored(&self) -> impl Iterator<Item = &QueryReport> {
+        self.queries
+            .iter()
+            .filter(|q| matches!(q.outcome, Outcome::Errored(_)))
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed().count() == 0 && self.errored().count() == 0
+    }
+
+    pub fn print_summary(&self) {
+        // Stdout going away mid-summary leaves nothing to report the failure to
+        let _ = self.write_summary(&mut io::stdout());
+    }
+
+    fn write_summary(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "\n========== Summary ==========")?;
+
+        let errored: Vec<&QueryReport> = self.errored().collect();
+        if !errored.is_empty() {
+            writeln!(out, "\n💥 Errored ({}):", errored.len())?;
+            for query in errored {
+                if let Outcome::Errored(err) = &query.outcome {
+                    writeln!(out, "  {} -> {}", query.name, err)?;
+                }
+            }
+        }
+
+        let failed: Vec<&QueryReport> = self.failed().collect();
+        if !failed.is_empty() {
+            writeln!(out, "\n❌ Failed ({}):", failed.len())?;
+            for query in failed {
+                writeln!(out, "  {}", query.name)?;
+            }
+        }
+
+        let passed: Vec<&QueryReport> = self.passed().collect();
+        if !passed.is_empty() {
+            writeln!(out, "\n✅ Passed ({}):", passed.len())?;
+            for query in passed {
+                writeln!(out, "  {}", query.name)?;
+            }
+        }
+
+        writeln!(
+            out,
+            "\nRan {} query(s), {} passed, {} failed, {} errored",
+            self.queries.len(),
+            self.passed().count(),
+            self.failed().count(),
+            self.errored().count()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarises_outcomes() {
+        let mut report = BatchReport::default();
+        report.record("login".to_string(), Outcome::Passed);
+        report.record("profile".to_string(), Outcome::Failed);
+        report.record(
+            "upload".to_string(),
+            Outcome::Errored("timed out".to_string()),
+        );
+        report.record("logout".to_string(), Outcome::Passed);
+        assert!(!report.is_success());
+
+        let mut out = Vec::new();
+        report.write_summary(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\n========== Summary ==========\n\
+             \n💥 Errored (1):\n  upload -> timed out\n\
+             \n❌ Failed (1):\n  profile\n\
+             \n✅ Passed (2):\n  login\n  logout\n\
+             \nRan 4 query(s), 2 passed, 1 failed, 1 errored\n"
+        );
+    }
+
+    #[test]
+    fn succeeds_only_when_everything_passed() {
+        let mut report = BatchReport::default();
+        assert!(report.is_success());
+        report.record("login".to_string(), Outcome::Passed);
+        assert!(report.is_success());
+
+        let mut out = Vec::new();
+        report.write_summary(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(!out.contains("Failed") && !out.contains("Errored"));
+        assert!(out.ends_with("Ran 1 query(s), 1 passed, 0 failed, 0 errored\n"));
+    }
+}
@@ -0,0 +1,249 @@
+use serde_json::{Map, Value, json};
+
+/// One call of an `<rpc>` block, several calls in a query being sent as a batch.
+#[derive(Debug, Clone)]
+pub struct RpcCall {
+    /// Assigned in script order, unique across the run.
+    pub id: u64,
+    pub method: String,
+    pub params: Option<Value>,
+}
+
+/// Parses the `params` of a call, which JSON-RPC 2.0 requires to be an array or an object.
+pub fn parse_params(value: &str) -> Result<Value, String> {
+    match serde_json::from_str(value) {
+        Ok(params @ (Value::Array(_) | Value::Object(_))) => Ok(params),
+        Ok(_) => Err(format!(
+            "RPC params must be an array or an object, found {}",
+            value
+        )),
+        Err(e) => Err(format!("Invalid JSON in RPC params: {}", e)),
+    }
+}
+
+fn envelope(call: &RpcCall) -> Value {
+    let mut envelope = Map::new();
+    envelope.insert("jsonrpc".to_string(), json!("2.0"));
+    envelope.insert("method".to_string(), json!(call.method));
+    if let Some(params) = &call.params {
+        envelope.insert("params".to_string(), params.clone());
+    }
+    envelope.insert("id".to_string(), json!(call.id));
+    Value::Object(envelope)
+}
+
+/// A lone call goes out as an object, more as a batch array.
+pub fn encode(calls: &[RpcCall]) -> Vec<u8> {
+    let body = match calls {
+        [call] => envelope(call),
+        calls => Value::Array(calls.iter().map(envelope).collect()),
+    };
+    body.to_string().into_bytes()
+}
+
+/// The response object answering each call, servers being free to reorder a batch.
+fn responses_by_call<'a>(calls: &[RpcCall], json: &'a Value) -> Vec<Option<&'a Value>> {
+    let responses: Vec<&Value> = match json {
+        Value::Array(items) => items.iter().collect(),
+        single => vec![single],
+    };
+
+    calls
+        .iter()
+        .map(|call| {
+            responses
+                .iter()
+                .find(|response| response.get("id").and_then(Value::as_u64) == Some(call.id))
+                .copied()
+        })
+        .collect()
+}
+
+/// The `result` of every call in call order, `null` for calls that got none.
+pub fn results(calls: &[RpcCall], body: &str) -> Vec<Value> {
+    let Ok(json) = serde_json::from_str::<Value>(body) else {
+        return Vec::new();
+    };
+
+    responses_by_call(calls, &json)
+        .into_iter()
+        .map(|response| {
+            response
+                .and_then(|response| response.get("result"))
+                .cloned()
+                .unwrap_or(Value::Null)
+        })
+        .collect()
+}
+
+/// `code message data` of an `error` object.
+fn describe_error(error: &Value) -> String {
+    let code = error.get("code").map(Value::to_string).unwrap_or_default();
+    let message = error
+        .get("message")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let mut line = format!("error {} {}", code, message);
+    if let Some(data) = error.get("data") {
+        line.push_str(&format!(" {}", data));
+    }
+    line
+}
+
+/// Fails with a line for every call that got an `error` object or no response at all, and for
+/// every error the server could not tie to a call, which it sends with a `null` id.
+pub fn check_errors(calls: &[RpcCall], body: &str) -> Result<(), Vec<String>> {
+    let Ok(json) = serde_json::from_str::<Value>(body) else {
+        return Err(vec!["Response is not JSON".to_string()]);
+    };
+
+    let mut problems: Vec<String> = calls
+        .iter()
+        .zip(responses_by_call(calls, &json))
+        .filter_map(|(call, response)| {
+            let label = format!("call {} (id {})", call.method, call.id);
+            let Some(response) = response else {
+                return Some(format!("{}: no response with this id", label));
+            };
+            let error = response.get("error")?;
+            Some(format!("{}: {}", label, describe_error(error)))
+        })
+        .collect();
+
+    let responses: Vec<&Value> = match &json {
+        Value::Array(items) => items.iter().collect(),
+        single => vec![single],
+    };
+    problems.extend(
+        responses
+            .into_iter()
+            .filter(|response| response.get("id").is_none_or(Value::is_null))
+            .filter_map(|response| response.get("error"))
+            .map(|error| format!("response without an id: {}", describe_error(error))),
+    );
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(id: u64, method: &str, params: Option<Value>) -> RpcCall {
+        RpcCall {
+            id,
+            method: method.to_string(),
+            params,
+        }
+    }
+
+    fn encoded(calls: &[RpcCall]) -> Value {
+        serde_json::from_slice(&encode(calls)).unwrap()
+    }
+
+    #[test]
+    fn encodes_a_lone_call_as_an_object() {
+        assert_eq!(
+            encoded(&[call(1, "sum", Some(json!([1, 2])))]),
+            json!({"jsonrpc": "2.0", "method": "sum", "params": [1, 2], "id": 1})
+        );
+        assert_eq!(
+            encoded(&[call(4, "ping", None)]),
+            json!({"jsonrpc": "2.0", "method": "ping", "id": 4})
+        );
+    }
+
+    #[test]
+    fn encodes_several_calls_as_a_batch() {
+        assert_eq!(
+            encoded(&[
+                call(1, "ping", None),
+                call(2, "get", Some(json!({"k": "v"})))
+            ]),
+            json!([
+                {"jsonrpc": "2.0", "method": "ping", "id": 1},
+                {"jsonrpc": "2.0", "method": "get", "params": {"k": "v"}, "id": 2}
+            ])
+        );
+    }
+
+    #[test]
+    fn params_must_be_structured() {
+        assert_eq!(parse_params("[1]"), Ok(json!([1])));
+        assert!(
+            parse_params("1")
+                .unwrap_err()
+                .contains("array or an object")
+        );
+        assert!(parse_params("[1").unwrap_err().contains("Invalid JSON"));
+    }
+
+    #[test]
+    fn matches_results_out_of_order() {
+        let calls = [call(1, "a", None), call(2, "b", None), call(3, "c", None)];
+        let body = r#"[
+            {"jsonrpc": "2.0", "id": 3, "result": "c"},
+            {"jsonrpc": "2.0", "id": 1, "result": {"n": 1}}
+        ]"#;
+        assert_eq!(
+            results(&calls, body),
+            [json!({"n": 1}), Value::Null, json!("c")]
+        );
+        assert_eq!(
+            check_errors(&calls, body),
+            Err(vec!["call b (id 2): no response with this id".to_string()])
+        );
+    }
+
+    #[test]
+    fn reports_error_objects() {
+        let calls = [call(1, "a", None), call(2, "b", None)];
+        let body = r#"[
+            {"jsonrpc": "2.0", "id": 2, "error": {"code": -32601, "message": "Method not found"}},
+            {"jsonrpc": "2.0", "id": 1, "error": {"code": -32602, "message": "Invalid params", "data": [1]}}
+        ]"#;
+        assert_eq!(
+            check_errors(&calls, body),
+            Err(vec![
+                "call a (id 1): error -32602 Invalid params [1]".to_string(),
+                "call b (id 2): error -32601 Method not found".to_string(),
+            ])
+        );
+        assert_eq!(
+            check_errors(&calls[..1], r#"{"jsonrpc": "2.0", "id": 1, "result": 0}"#),
+            Ok(())
+        );
+        assert_eq!(
+            check_errors(&calls, "<html>"),
+            Err(vec!["Response is not JSON".to_string()])
+        );
+    }
+
+    #[test]
+    fn reports_errors_without_an_id() {
+        let calls = [call(1, "a", None)];
+        let body = r#"{"jsonrpc": "2.0", "id": null, "error": {"code": -32700, "message": "Parse error"}}"#;
+        assert_eq!(
+            check_errors(&calls, body),
+            Err(vec![
+                "call a (id 1): no response with this id".to_string(),
+                "response without an id: error -32700 Parse error".to_string(),
+            ])
+        );
+
+        let body = r#"[
+            {"jsonrpc": "2.0", "id": 1, "result": true},
+            {"jsonrpc": "2.0", "error": {"code": -32600, "message": "Invalid Request"}}
+        ]"#;
+        assert_eq!(
+            check_errors(&calls, body),
+            Err(vec![
+                "response without an id: error -32600 Invalid Request".to_string()
+            ])
+        );
+    }
+}
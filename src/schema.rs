@@ -0,0 +1,738 @@
+use std::{cell::RefCell, collections::HashSet, fmt, fs};
+
+use regex::Regex;
+use serde_json::{Map, Value};
+
+/// A single mismatch between a document and its schema, located by a JSON pointer.
+#[derive(Debug)]
+pub struct Violation {
+    pub pointer: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}: {}", self.pointer, self.message)
+    }
+}
+
+pub fn load_schema(path: &str) -> Result<Value, String> {
+    let raw =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read schema {}: {}", path, e))?;
+    serde_json::from_str(&raw).map_err(|e| format!("Failed to parse schema {}: {}", path, e))
+}
+
+/// Validates `instance` against a draft 2020-12 schema, collecting every violation.
+///
+/// Supported keywords: `type`, `enum`, `const`, `properties`, `patternProperties`, `required`,
+/// `additionalProperties`, `propertyNames`, `dependentRequired`, `dependentSchemas`,
+/// `minProperties`, `maxProperties`, `items`, `prefixItems`, `contains`, `minContains`,
+/// `maxContains`, `minItems`, `maxItems`, `uniqueItems`, `minLength`, `maxLength`, `pattern`,
+/// `minimum`, `maximum`, `exclusiveMinimum`, `exclusiveMaximum`, `multipleOf`,
+/// `allOf`, `anyOf`, `oneOf`, `not`, `if`/`then`/`else` and local `$ref`s into the same
+/// document.
+pub fn validate(schema: &Value, instance: &Value) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let validator = Validator {
+        root: schema,
+        expanding: RefCell::default(),
+    };
+    validator.check(schema, instance, "", &mut violations);
+    violations
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+    /// Instance pointers and the `$ref`s being expanded there, a repeat being a cycle.
+    expanding: RefCell<Vec<(String, String)>>,
+}
+
+impl<'a> Validator<'a> {
+    fn check(&self, schema: &Value, instance: &Value, pointer: &str, out: &mut Vec<Violation>) {
+        let schema = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => {
+                return report(out, pointer, "no value is allowed here".to_string());
+            }
+            Value::Object(schema) => schema,
+            _ => {
+                return report(
+                    out,
+                    pointer,
+                    "schema must be an object or boolean".to_string(),
+                );
+            }
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            self.check_ref(reference, instance, pointer, out);
+        }
+
+        if let Some(expected) = schema.get("type")
+            && !matches_type(expected, instance)
+        {
+            report(
+                out,
+                pointer,
+                format!("expected type {}, found {}", expected, type_name(instance)),
+            );
+        }
+
+        if let Some(Value::Array(options)) = schema.get("enum")
+            && !options.contains(instance)
+        {
+            report(
+                out,
+                pointer,
+                format!(
+                    "{} is not one of {}",
+                    instance,
+                    Value::Array(options.clone())
+                ),
+            );
+        }
+
+        if let Some(expected) = schema.get("const")
+            && expected != instance
+        {
+            report(
+                out,
+                pointer,
+                format!("expected {}, found {}", expected, instance),
+            );
+        }
+
+        match instance {
+            Value::Object(object) => self.check_object(schema, object, pointer, out),
+            Value::Array(items) => self.check_array(schema, items, pointer, out),
+            Value::String(string) => check_string(schema, string, pointer, out),
+            Value::Number(_) => check_number(schema, instance, pointer, out),
+            _ => {}
+        }
+
+        self.check_combinators(schema, instance, pointer, out);
+    }
+
+    fn check_ref(
+        &self,
+        reference: &str,
+        instance: &Value,
+        pointer: &str,
+        out: &mut Vec<Violation>,
+    ) {
+        let Some(target) = self.resolve(reference) else {
+            return report(out, pointer, format!("unresolvable $ref `{}`", reference));
+        };
+
+        let expansion = (pointer.to_string(), reference.to_string());
+        if self.expanding.borrow().contains(&expansion) {
+            return report(out, pointer, format!("circular $ref `{}`", reference));
+        }
+        self.expanding.borrow_mut().push(expansion);
+        self.check(target, instance, pointer, out);
+        self.expanding.borrow_mut().pop();
+    }
+
+    fn check_object(
+        &self,
+        schema: &Map<String, Value>,
+        object: &Map<String, Value>,
+        pointer: &str,
+        out: &mut Vec<Violation>,
+    ) {
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for key in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(key) {
+                    report(out, pointer, format!("missing required property `{}`", key));
+                }
+            }
+        }
+
+        if let Some(Value::Object(dependents)) = schema.get("dependentRequired") {
+            for (trigger, required) in dependents {
+                if !object.contains_key(trigger) {
+                    continue;
+                }
+                let required = required.as_array().map(Vec::as_slice).unwrap_or_default();
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(key) {
+                        report(
+                            out,
+                            pointer,
+                            format!("property `{}` requires property `{}`", trigger, key),
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut patterns = Vec::new();
+        if let Some(Value::Object(pattern_properties)) = schema.get("patternProperties") {
+            for (pattern, property) in pattern_properties {
+                match Regex::new(pattern) {
+                    Ok(re) => patterns.push((re, property)),
+                    Err(e) => report(
+                        out,
+                        pointer,
+                        format!("invalid pattern `{}`: {}", pattern, e),
+                    ),
+                }
+            }
+        }
+
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for (key, value) in object {
+            let child = join_pointer(pointer, key);
+            let mut is_evaluated = false;
+            if let Some(property) = properties.and_then(|p| p.get(key)) {
+                self.check(property, value, &child, out);
+                is_evaluated = true;
+            }
+            for (_, property) in patterns.iter().filter(|(re, _)| re.is_match(key)) {
+                self.check(property, value, &child, out);
+                is_evaluated = true;
+            }
+            if !is_evaluated && let Some(additional) = schema.get("additionalProperties") {
+                self.check(additional, value, &child, out);
+            }
+
+            if let Some(names) = schema.get("propertyNames")
+                && !self.is_valid(names, &Value::String(key.clone()), &child)
+            {
+                report(
+                    out,
+                    pointer,
+                    format!("property name `{}` does not match propertyNames", key),
+                );
+            }
+        }
+
+        check_bounds(
+            schema,
+            "minProperties",
+            "maxProperties",
+            object.len(),
+            "properties",
+            pointer,
+            out,
+        );
+    }
+
+    fn check_array(
+        &self,
+        schema: &Map<String, Value>,
+        items: &[Value],
+        pointer: &str,
+        out: &mut Vec<Violation>,
+    ) {
+        let prefix = schema
+            .get("prefixItems")
+            .and_then(Value::as_array)
+            .map(|p| p.as_slice())
+            .unwrap_or_default();
+
+        for (index, item) in items.iter().enumerate() {
+            let child = join_pointer(pointer, &index.to_string());
+            match prefix.get(index) {
+                Some(item_schema) => self.check(item_schema, item, &child, out),
+                None => {
+                    if let Some(item_schema) = schema.get("items") {
+                        self.check(item_schema, item, &child, out);
+                    }
+                }
+            }
+        }
+
+        check_bounds(
+            schema,
+            "minItems",
+            "maxItems",
+            items.len(),
+            "items",
+            pointer,
+            out,
+        );
+
+        if let Some(contains) = schema.get("contains") {
+            let matched = items
+                .iter()
+                .enumerate()
+                .filter(|(index, item)| {
+                    self.is_valid(contains, item, &join_pointer(pointer, &index.to_string()))
+                })
+                .count();
+            let min = schema
+                .get("minContains")
+                .and_then(Value::as_u64)
+                .unwrap_or(1);
+            if (matched as u64) < min {
+                report(
+                    out,
+                    pointer,
+                    format!(
+                        "expected at least {} items matching contains, found {}",
+                        min, matched
+                    ),
+                );
+            }
+            if let Some(max) = schema.get("maxContains").and_then(Value::as_u64)
+                && (matched as u64) > max
+            {
+                report(
+                    out,
+                    pointer,
+                    format!(
+                        "expected at most {} items matching contains, found {}",
+                        max, matched
+                    ),
+                );
+            }
+        }
+
+        if schema.get("uniqueItems") == Some(&Value::Bool(true)) {
+            let mut seen = HashSet::new();
+            if !items.iter().all(|item| seen.insert(item.to_string())) {
+                report(out, pointer, "items are not unique".to_string());
+            }
+        }
+    }
+
+    fn check_combinators(
+        &self,
+        schema: &Map<String, Value>,
+        instance: &Value,
+        pointer: &str,
+        out: &mut Vec<Violation>,
+    ) {
+        if let Some(Value::Array(all)) = schema.get("allOf") {
+            for sub in all {
+                self.check(sub, instance, pointer, out);
+            }
+        }
+
+        if let Some(Value::Array(any)) = schema.get("anyOf")
+            && !any.iter().any(|sub| self.is_valid(sub, instance, pointer))
+        {
+            report(
+                out,
+                pointer,
+                "does not match any schema in anyOf".to_string(),
+            );
+        }
+
+        if let Some(Value::Array(one)) = schema.get("oneOf") {
+            let matched = one
+                .iter()
+                .filter(|sub| self.is_valid(sub, instance, pointer))
+                .count();
+            if matched != 1 {
+                report(
+                    out,
+                    pointer,
+                    format!(
+                        "must match exactly one schema in oneOf, matched {}",
+                        matched
+                    ),
+                );
+            }
+        }
+
+        if let Some(not) = schema.get("not")
+            && self.is_valid(not, instance, pointer)
+        {
+            report(out, pointer, "must not match the schema in not".to_string());
+        }
+
+        if let (Some(Value::Object(dependents)), Value::Object(object)) =
+            (schema.get("dependentSchemas"), instance)
+        {
+            for (trigger, dependent) in dependents {
+                if object.contains_key(trigger) {
+                    self.check(dependent, instance, pointer, out);
+                }
+            }
+        }
+
+        if let Some(condition) = schema.get("if") {
+            let branch = if self.is_valid(condition, instance, pointer) {
+                schema.get("then")
+            } else {
+                schema.get("else")
+            };
+            if let Some(branch) = branch {
+                self.check(branch, instance, pointer, out);
+            }
+        }
+    }
+
+    /// Checks without reporting, `pointer` locating the instance for the `$ref` cycle guard.
+    fn is_valid(&self, schema: &Value, instance: &Value, pointer: &str) -> bool {
+        let mut violations = Vec::new();
+        self.check(schema, instance, pointer, &mut violations);
+        violations.is_empty()
+    }
+
+    fn resolve(&self, reference: &str) -> Option<&'a Value> {
+        let pointer = reference.strip_prefix('#')?;
+        self.root.pointer(pointer)
+    }
+}
+
+fn check_string(
+    schema: &Map<String, Value>,
+    string: &str,
+    pointer: &str,
+    out: &mut Vec<Violation>,
+) {
+    let length = string.chars().count();
+    check_bounds(
+        schema,
+        "minLength",
+        "maxLength",
+        length,
+        "characters",
+        pointer,
+        out,
+    );
+
+    if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+        match Regex::new(pattern) {
+            Ok(re) if !re.is_match(string) => {
+                report(
+                    out,
+                    pointer,
+                    format!("\"{}\" does not match pattern `{}`", string, pattern),
+                );
+            }
+            Err(e) => report(
+                out,
+                pointer,
+                format!("invalid pattern `{}`: {}", pattern, e),
+            ),
+            _ => {}
+        }
+    }
+}
+
+fn check_number(
+    schema: &Map<String, Value>,
+    instance: &Value,
+    pointer: &str,
+    out: &mut Vec<Violation>,
+) {
+    let Some(number) = instance.as_f64() else {
+        return;
+    };
+    let limit = |key: &str| schema.get(key).and_then(Value::as_f64);
+
+    if let Some(min) = limit("minimum")
+        && number < min
+    {
+        report(
+            out,
+            pointer,
+            format!("{} is less than minimum {}", number, min),
+        );
+    }
+    if let Some(max) = limit("maximum")
+        && number > max
+    {
+        report(
+            out,
+            pointer,
+            format!("{} is greater than maximum {}", number, max),
+        );
+    }
+    if let Some(min) = limit("exclusiveMinimum")
+        && number <= min
+    {
+        report(
+            out,
+            pointer,
+            format!("{} must be greater than {}", number, min),
+        );
+    }
+    if let Some(max) = limit("exclusiveMaximum")
+        && number >= max
+    {
+        report(
+            out,
+            pointer,
+            format!("{} must be less than {}", number, max),
+        );
+    }
+    if let Some(divisor) = limit("multipleOf")
+        && divisor != 0.0
+        && (number / divisor).fract() != 0.0
+    {
+        report(
+            out,
+            pointer,
+            format!("{} is not a multiple of {}", number, divisor),
+        );
+    }
+}
+
+fn check_bounds(
+    schema: &Map<String, Value>,
+    min_key: &str,
+    max_key: &str,
+    count: usize,
+    unit: &str,
+    pointer: &str,
+    out: &mut Vec<Violation>,
+) {
+    if let Some(min) = schema.get(min_key).and_then(Value::as_u64)
+        && (count as u64) < min
+    {
+        report(
+            out,
+            pointer,
+            format!("expected at least {} {}, found {}", min, unit, count),
+        );
+    }
+    if let Some(max) = schema.get(max_key).and_then(Value::as_u64)
+        && (count as u64) > max
+    {
+        report(
+            out,
+            pointer,
+            format!("expected at most {} {}, found {}", max, unit, count),
+        );
+    }
+}
+
+fn matches_type(expected: &Value, instance: &Value) -> bool {
+    match expected {
+        Value::String(name) => is_type(name, instance),
+        Value::Array(names) => names
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|name| is_type(name, instance)),
+        _ => true,
+    }
+}
+
+fn is_type(name: &str, instance: &Value) -> bool {
+    match name {
+        "integer" => instance.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "number" => instance.is_number(),
+        other => other == type_name(instance),
+    }
+}
+
+fn type_name(instance: &Value) -> &'static str {
+    match instance {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+pub fn join_pointer(pointer: &str, token: &str) -> String {
+    format!(
+        "{}/{}",
+        pointer,
+        token.replace('~', "~0").replace('/', "~1")
+    )
+}
+
+fn report(out: &mut Vec<Violation>, pointer: &str, message: String) {
+    out.push(Violation {
+        pointer: pointer.to_string(),
+        message,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn messages(schema: Value, instance: Value) -> Vec<String> {
+        validate(&schema, &instance)
+            .iter()
+            .map(|v| v.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn locates_object_violations_by_pointer() {
+        let schema = json!({
+            "type": "object",
+            "required": ["id", "name"],
+            "properties": {
+                "id": {"type": "integer", "minimum": 1},
+                "a/b": {"type": "string"}
+            },
+            "additionalProperties": {"type": "boolean"}
+        });
+        assert!(messages(schema.clone(), json!({"id": 1, "name": true})).is_empty());
+        assert_eq!(
+            messages(schema.clone(), json!({"id": 0.5, "a/b": 1, "x~y": "no"})),
+            [
+                "#: missing required property `name`",
+                "#/a~1b: expected type \"string\", found number",
+                "#/id: expected type \"integer\", found number",
+                "#/id: 0.5 is less than minimum 1",
+                "#/x~0y: expected type \"boolean\", found string"
+            ]
+        );
+        assert_eq!(
+            messages(schema, json!([])),
+            ["#: expected type \"object\", found array"]
+        );
+    }
+
+    #[test]
+    fn checks_array_items_and_bounds() {
+        let schema = json!({
+            "prefixItems": [{"const": "head"}],
+            "items": {"type": "number", "multipleOf": 2},
+            "maxItems": 3,
+            "uniqueItems": true
+        });
+        assert!(messages(schema.clone(), json!(["head", 2, 4])).is_empty());
+        assert_eq!(
+            messages(schema, json!(["tail", 3, 2, 2])),
+            [
+                "#/0: expected \"head\", found \"tail\"",
+                "#/1: 3 is not a multiple of 2",
+                "#: expected at most 3 items, found 4",
+                "#: items are not unique"
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_strings_and_enums() {
+        let schema = json!({"minLength": 2, "pattern": "^[a-z]+$", "enum": ["ab", "Z"]});
+        assert!(messages(schema.clone(), json!("ab")).is_empty());
+        assert_eq!(
+            messages(schema, json!("Z")),
+            [
+                "#: expected at least 2 characters, found 1",
+                "#: \"Z\" does not match pattern `^[a-z]+$`"
+            ]
+        );
+        assert_eq!(
+            messages(json!({"enum": [1, 2]}), json!(3)),
+            ["#: 3 is not one of [1,2]"]
+        );
+    }
+
+    #[test]
+    fn combines_schemas_and_follows_refs() {
+        let schema = json!({
+            "$defs": {"id": {"type": "integer"}},
+            "properties": {
+                "id": {"$ref": "#/$defs/id"},
+                "either": {"anyOf": [{"type": "string"}, {"type": "null"}]},
+                "one": {"oneOf": [{"minimum": 0}, {"maximum": 10}]},
+                "other": {"not": {"type": "string"}},
+                "lost": {"$ref": "#/$defs/missing"}
+            }
+        });
+        assert!(messages(schema.clone(), json!({"id": 1, "either": null, "one": 20})).is_empty());
+        assert_eq!(
+            messages(
+                schema,
+                json!({"id": "1", "either": 1, "one": 5, "other": "s", "lost": 1})
+            ),
+            [
+                "#/either: does not match any schema in anyOf",
+                "#/id: expected type \"integer\", found string",
+                "#/lost: unresolvable $ref `#/$defs/missing`",
+                "#/one: must match exactly one schema in oneOf, matched 2",
+                "#/other: must not match the schema in not"
+            ]
+        );
+    }
+
+    #[test]
+    fn pattern_properties_are_not_additional() {
+        let schema = json!({
+            "patternProperties": {"^x-": {"type": "string"}},
+            "additionalProperties": false
+        });
+        assert!(messages(schema.clone(), json!({"x-a": "ok"})).is_empty());
+        assert_eq!(
+            messages(schema, json!({"x-a": 1, "y": "no"})),
+            [
+                "#/x-a: expected type \"string\", found number",
+                "#/y: no value is allowed here"
+            ]
+        );
+    }
+
+    #[test]
+    fn property_names_and_dependent_required() {
+        let schema = json!({
+            "propertyNames": {"maxLength": 4},
+            "dependentRequired": {"card": ["cvv"]}
+        });
+        assert!(messages(schema.clone(), json!({"card": 1, "cvv": 2})).is_empty());
+        assert_eq!(
+            messages(schema, json!({"card": 1, "expiry": 2})),
+            [
+                "#: property `card` requires property `cvv`",
+                "#: property name `expiry` does not match propertyNames"
+            ]
+        );
+    }
+
+    #[test]
+    fn contains_counts_matching_items() {
+        let schema = json!({"contains": {"const": 1}, "maxContains": 2});
+        assert!(messages(schema.clone(), json!([0, 1])).is_empty());
+        assert_eq!(
+            messages(schema.clone(), json!([0])),
+            ["#: expected at least 1 items matching contains, found 0"]
+        );
+        assert_eq!(
+            messages(schema, json!([1, 1, 1])),
+            ["#: expected at most 2 items matching contains, found 3"]
+        );
+    }
+
+    #[test]
+    fn if_picks_then_or_else() {
+        let schema = json!({
+            "if": {"properties": {"kind": {"const": "card"}}},
+            "then": {"required": ["number"]},
+            "else": {"required": ["iban"]}
+        });
+        assert!(messages(schema.clone(), json!({"kind": "card", "number": 4})).is_empty());
+        assert_eq!(
+            messages(schema, json!({"kind": "bank"})),
+            ["#: missing required property `iban`"]
+        );
+    }
+
+    #[test]
+    fn circular_ref_is_a_violation() {
+        assert_eq!(
+            messages(json!({"$ref": "#"}), json!({})),
+            ["#: circular $ref `#`"]
+        );
+        let schema = json!({"anyOf": [{"$ref": "#"}]});
+        assert!(!messages(schema, json!(1)).is_empty());
+    }
+
+    #[test]
+    fn recursive_ref_descends_into_values() {
+        let schema = json!({
+            "$defs": {"node": {
+                "type": "object",
+                "properties": {"next": {"anyOf": [{"type": "null"}, {"$ref": "#/$defs/node"}]}}
+            }},
+            "$ref": "#/$defs/node"
+        });
+        assert!(messages(schema.clone(), json!({"next": {"next": null}})).is_empty());
+        assert!(!messages(schema, json!({"next": {"next": 3}})).is_empty());
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    env::current_dir,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+
+use crate::{diff::line_diff, http::Response};
+
+const IGNORED_PLACEHOLDER: &str = "[ignored]";
+
+#[derive(Default, Debug)]
+pub struct Snapshot {
+    pub name: String,
+    /// Response headers recorded next to the status, everything else is left out.
+    pub headers: Vec<String>,
+    /// Dotted JSON paths replaced with a placeholder, `*` matching any key or index.
+    pub ignore: Vec<String>,
+    pub update: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SnapshotStatus {
+    Matched,
+    Written,
+    Updated,
+}
+
+impl Snapshot {
+    /// The name with every character unsafe in a file name replaced, so two names can map to
+    /// the same file.
+    pub fn file_name(&self) -> String {
+        self.name
+            .chars()
+            .map(|ch| {
+                if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' || ch == '.' {
+                    ch
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+
+    fn path(&self, root: &Path) -> PathBuf {
+        let file_name = self.file_name();
+        let mut path = root.to_path_buf();
+        path.push(".nethop");
+        path.push("__snapshots__");
+        path.push(format!("{}.snap", file_name));
+        path
+    }
+
+    /// Renders the parts of the response the snapshot tracks.
+    fn render(&self, response: &Response) -> String {
+        let mut rendered = format!("status: {}\n", response.status);
+        for header in &self.headers {
+            rendered.push_str(&format!(
+                "{}: {}\n",
+                header.to_lowercase(),
+                response.get_header(header).unwrap_or("--")
+            ));
+        }
+        rendered.push('\n');
+
+        match serde_json::from_str::<Value>(&response.body) {
+            Ok(mut json) => {
+                for path in &self.ignore {
+                    let segments: Vec<&str> = path.split('.').collect();
+                    mask_path(&mut json, &segments);
+                }
+                rendered.push_str(&serde_json::to_string_pretty(&json).unwrap_or_default());
+            }
+            Err(_) => rendered.push_str(&response.body),
+        }
+        rendered.push('\n');
+
+        rendered
+    }
+
+    /// Compares the response with the stored snapshot, writing it when missing or updating.
+    pub fn check(&self, response: &Response) -> Result<SnapshotStatus, Vec<String>> {
+        let root = current_dir()
+            .map_err(|_| vec!["Failed to get current working directory".to_string()])?;
+        self.check_in(&root, response)
+    }
+
+    /// Same as `check`, with `.nethop/__snapshots__` under `root`.
+    fn check_in(&self, root: &Path, response: &Response) -> Result<SnapshotStatus, Vec<String>> {
+        let path = self.path(root);
+        let rendered = self.render(response);
+        let write = || {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)
+                    .map_err(|e| vec![format!("Failed to create {}: {}", dir.display(), e)])?;
+            }
+            fs::write(&path, &rendered)
+                .map_err(|e| vec![format!("Failed to write {}: {}", path.display(), e)])
+        };
+
+        if !path.exists() {
+            write()?;
+            return Ok(SnapshotStatus::Written);
+        }
+
+        let stored = fs::read_to_string(&path)
+            .map_err(|e| vec![format!("Failed to read {}: {}", path.display(), e)])?;
+        if stored == rendered {
+            return Ok(SnapshotStatus::Matched);
+        }
+
+        if self.update {
+            write()?;
+            return Ok(SnapshotStatus::Updated);
+        }
+
+        let mut details = vec![format!(
+            "Snapshot {} differs, rerun with --update-snapshots to accept",
+            path.display()
+        )];
+        details.extend(line_diff(&stored, &rendered));
+        Err(details)
+    }
+}
+
+fn mask_path(json: &mut Value, segments: &[&str]) {
+    let Some((first, rest)) = segments.split_first() else {
+        return;
+    };
+
+    let children: Vec<&mut Value> = match (json, *first) {
+        (Value::Object(map), "*") => map.values_mut().collect(),
+        (Value::Array(items), "*") => items.iter_mut().collect(),
+        (Value::Object(map), key) => map.get_mut(key).into_iter().collect(),
+        (Value::Array(items), index) => index
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| items.get_mut(i))
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    for child in children {
+        if rest.is_empty() {
+            *child = Value::String(IGNORED_PLACEHOLDER.to_string());
+        } else {
+            mask_path(child, rest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn response(body: &str) -> Response {
+        let mut response = Response::new(
+            "HTTP/1.1",
+            200,
+            vec![
+                ("Content-Type".to_string(), "application/json".to_string()),
+                (
+                    "Date".to_string(),
+                    "Mon, 19 Oct 2026 10:00:00 GMT".to_string(),
+                ),
+            ],
+        );
+        response.body = body.to_string();
+        response
+    }
+
+    fn snapshot(name: &str) -> Snapshot {
+        Snapshot {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn masked(mut json: Value, path: &str) -> Value {
+        let segments: Vec<&str> = path.split('.').collect();
+        mask_path(&mut json, &segments);
+        json
+    }
+
+    #[test]
+    fn renders_selected_headers_and_pretty_json() {
+        let users = Snapshot {
+            headers: vec!["Content-Type".to_string(), "X-Missing".to_string()],
+            ..snapshot("users")
+        };
+        assert_eq!(
+            users.render(&response(r#"{"id":1,"tags":["a"]}"#)),
+            "status: 200\ncontent-type: application/json\nx-missing: --\n\n\
+             {\n  \"id\": 1,\n  \"tags\": [\n    \"a\"\n  ]\n}\n"
+        );
+        assert_eq!(
+            snapshot("text").render(&response("plain text")),
+            "status: 200\n\nplain text\n"
+        );
+    }
+
+    #[test]
+    fn masks_keys_and_indexes() {
+        let json = json!({"id": 1, "items": [{"at": 1, "n": 2}, {"at": 3, "n": 4}]});
+        assert_eq!(
+            masked(json.clone(), "id"),
+            json!({"id": "[ignored]", "items": [{"at": 1, "n": 2}, {"at": 3, "n": 4}]})
+        );
+        assert_eq!(
+            masked(json.clone(), "items.*.at"),
+            json!({"id": 1, "items": [{"at": "[ignored]", "n": 2}, {"at": "[ignored]", "n": 4}]})
+        );
+        assert_eq!(
+            masked(json.clone(), "items.1.n"),
+            json!({"id": 1, "items": [{"at": 1, "n": 2}, {"at": 3, "n": "[ignored]"}]})
+        );
+        assert_eq!(
+            masked(json!({"a": {"x": 1}, "b": {"x": 2, "y": 3}}), "*.x"),
+            json!({"a": {"x": "[ignored]"}, "b": {"x": "[ignored]", "y": 3}})
+        );
+    }
+
+    #[test]
+    fn leaves_missing_paths_alone() {
+        let json = json!({"id": 1, "items": [{"at": 1}]});
+        for path in [
+            "missing",
+            "id.deeper",
+            "items.5.at",
+            "items.x",
+            "items.*.missing",
+        ] {
+            assert_eq!(masked(json.clone(), path), json, "{}", path);
+        }
+    }
+
+    #[test]
+    fn writes_then_matches_or_diffs() {
+        let root = std::env::temp_dir().join(format!("nethop-{}-snapshots", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let mut snapshot = Snapshot {
+            ignore: vec!["at".to_string()],
+            ..snapshot("get user/1")
+        };
+        let path = root.join(".nethop/__snapshots__/get_user_1.snap");
+
+        assert_eq!(
+            snapshot.check_in(&root, &response(r#"{"id":1,"at":5}"#)),
+            Ok(SnapshotStatus::Written)
+        );
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written.contains("\"at\": \"[ignored]\""));
+        assert_eq!(
+            snapshot.check_in(&root, &response(r#"{"id":1,"at":6}"#)),
+            Ok(SnapshotStatus::Matched)
+        );
+
+        let details = snapshot
+            .check_in(&root, &response(r#"{"id":2,"at":6}"#))
+            .unwrap_err();
+        assert!(details[0].contains("rerun with --update-snapshots"));
+        assert!(details.contains(&"-   \"id\": 1".to_string()));
+        assert!(details.contains(&"+   \"id\": 2".to_string()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), written);
+
+        snapshot.update = true;
+        assert_eq!(
+            snapshot.check_in(&root, &response(r#"{"id":2,"at":6}"#)),
+            Ok(SnapshotStatus::Updated)
+        );
+        assert!(fs::read_to_string(&path).unwrap().contains("\"id\": 2"));
+        assert_eq!(
+            snapshot.check_in(&root, &response(r#"{"id":2,"at":7}"#)),
+            Ok(SnapshotStatus::Matched)
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,6 +1,14 @@
 use core::fmt;
+use std::path::Path;
 
-use crate::http::Response;
+use regex::RegexBuilder;
+use serde_json::Value;
+
+use crate::{
+    diff::{json_diff, line_diff},
+    http::{Response, SseEvent},
+    schema::{load_schema, validate},
+};
 
 #[derive(Default, Debug, Clone, Copy)]
 pub enum Operator {
@@ -9,7 +17,22 @@ pub enum Operator {
     Equals,
     NotEquals,
     Contains,
+    NotContains,
     StartsWith,
+    EndsWith,
+    Matches,
+    In,
+    EqualsIgnoreCase,
+    NotEqualsIgnoreCase,
+    ContainsIgnoreCase,
+    NotContainsIgnoreCase,
+    StartsWithIgnoreCase,
+    EndsWithIgnoreCase,
+    MatchesIgnoreCase,
+    InIgnoreCase,
+    MatchesSchema,
+    Exists,
+    NotExists,
     GreaterThan,
     SmallerThan,
     GreaterThanOrEqualTo,
@@ -27,7 +50,36 @@ impl Operator {
             Self::SmallerThan => "<",
             Self::SmallerThanOrEqualTo => "<=",
             Self::Contains => "~",
+            Self::NotContains => "!~",
             Self::StartsWith => "^",
+            Self::EndsWith => "$",
+            Self::Matches => "=~",
+            Self::In => "in",
+            Self::EqualsIgnoreCase => "=*",
+            Self::NotEqualsIgnoreCase => "!=*",
+            Self::ContainsIgnoreCase => "~*",
+            Self::NotContainsIgnoreCase => "!~*",
+            Self::StartsWithIgnoreCase => "^*",
+            Self::EndsWithIgnoreCase => "$*",
+            Self::MatchesIgnoreCase => "=~*",
+            Self::InIgnoreCase => "in*",
+            Self::MatchesSchema => "matches-schema",
+            Self::Exists => "exists",
+            Self::NotExists => "!exists",
+        }
+    }
+
+    /// The case sensitive counterpart of a `*` suffixed operator.
+    fn case_sensitive(&self) -> Option<Operator> {
+        match self {
+            Self::EqualsIgnoreCase => Some(Self::Equals),
+            Self::NotEqualsIgnoreCase => Some(Self::NotEquals),
+            Self::ContainsIgnoreCase => Some(Self::Contains),
+            Self::NotContainsIgnoreCase => Some(Self::NotContains),
+            Self::StartsWithIgnoreCase => Some(Self::StartsWith),
+            Self::EndsWithIgnoreCase => Some(Self::EndsWith),
+            Self::InIgnoreCase => Some(Self::In),
+            _ => None,
         }
     }
 }
@@ -43,23 +95,251 @@ pub struct TestCase {
     pub key: String,
     pub value: String,
     pub operation: Operator,
+    /// Compare the number of values found for `key` instead of the value itself.
+    pub count: bool,
+}
+
+impl fmt::Display for TestCase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.key)?;
+        if self.count {
+            write!(f, " count")?;
+        }
+        write!(f, " {}", self.operation)?;
+        if !self.value.is_empty() {
+            write!(f, " {}", self.value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits `header.set-cookie[1]` into the header name and the index of the repeated value.
+/// Bare keys other than `body`, `status`, timing phases, `event.<field>`, `data.<path>` and
+/// `result.<path>` are header names too.
+fn parse_header_key(key: &str) -> Result<(&str, Option<usize>), String> {
+    let key = key.strip_prefix("header.").unwrap_or(key);
+    match key.strip_suffix(']').and_then(|k| k.split_once('[')) {
+        Some((name, index)) => index
+            .parse::<usize>()
+            .map(|index| (name, Some(index)))
+            .map_err(|_| format!("Invalid header index in {}", key)),
+        None => Ok((key, None)),
+    }
 }
 
-pub fn test_case(response: &Response, case: &TestCase) -> bool {
-    print!(
-        "Case: {} {} {}",
-        case.key,
-        get_operator(case.operation.as_symbol()).unwrap_or(Operator::Unknown),
-        case.value
-    );
-    if case.key == "body" {
-        return do_operation(&case.operation, &response.body, &case.value);
-    } else if case.key == "status" {
-        return do_operation(&case.operation, &response.status.to_string(), &case.value);
+/// Every value `key` addresses in the response, several for repeated headers.
+fn resolve_values(response: &Response, key: &str) -> Vec<String> {
+    if !response.rpc_results.is_empty() && (key == "result" || key.starts_with("result.")) {
+        // A lone call is addressed directly, a batch by the position of the call
+        let results = match response.rpc_results.as_slice() {
+            [result] => result.clone(),
+            results => Value::Array(results.to_vec()),
+        };
+        let segments: Vec<&str> = key.split('.').skip(1).collect();
+        return select_path(&results, &segments)
+            .into_iter()
+            .map(json_value_text)
+            .collect();
+    } else if key == "data" || key.starts_with("data.") {
+        let Ok(json) = serde_json::from_str::<Value>(&response.body) else {
+            return Vec::new();
+        };
+        let segments: Vec<&str> = key.split('.').collect();
+        return select_path(&json, &segments)
+            .into_iter()
+            .map(json_value_text)
+            .collect();
+    } else if key == "body" {
+        return vec![response.body.clone()];
+    } else if key == "status" {
+        return vec![response.status.to_string()];
+    } else if key == "version" {
+        return vec![response.version.clone()];
+    } else if let Some(duration) = response.timings.get(key) {
+        return vec![(duration.as_secs_f64() * 1000.0).to_string()];
+    }
+
+    let Ok((name, index)) = parse_header_key(key) else {
+        return Vec::new();
+    };
+    if let Some(field) = name.strip_prefix("event.") {
+        let events = match index {
+            Some(index) => response.events.get(index..=index).unwrap_or_default(),
+            None => &response.events,
+        };
+        return events
+            .iter()
+            .filter_map(|event| event_field(event, field))
+            .collect();
+    }
+
+    let values: Vec<&str> = match name.strip_prefix("trailer.") {
+        Some(trailer) => response
+            .trailers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(trailer))
+            .map(|(_, value)| value.as_str())
+            .collect(),
+        None => response.get_headers(name),
+    };
+    match index {
+        Some(index) => values
+            .get(index)
+            .map(|v| v.to_string())
+            .into_iter()
+            .collect(),
+        None => values.iter().map(|v| v.to_string()).collect(),
+    }
+}
+
+/// Strings without their quotes, anything else as compact JSON.
+fn json_value_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Every value under a dotted JSON path, `*` matching any key or array index.
+fn select_path<'a>(json: &'a Value, segments: &[&str]) -> Vec<&'a Value> {
+    let Some((first, rest)) = segments.split_first() else {
+        return vec![json];
+    };
+
+    let children: Vec<&Value> = match (json, *first) {
+        (Value::Object(map), "*") => map.values().collect(),
+        (Value::Array(items), "*") => items.iter().collect(),
+        (Value::Object(map), key) => map.get(key).into_iter().collect(),
+        (Value::Array(items), index) => index
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| items.get(i))
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    children
+        .into_iter()
+        .flat_map(|child| select_path(child, rest))
+        .collect()
+}
+
+/// A field of a server-sent event, `None` for `id` and `retry` when the event did not set them.
+fn event_field(event: &SseEvent, field: &str) -> Option<String> {
+    match field {
+        "data" => Some(event.data.clone()),
+        "type" => Some(event.event.clone()),
+        "id" => event.id.clone(),
+        "retry" => event.retry.map(|retry| retry.to_string()),
+        _ => None,
+    }
+}
+
+/// Runs a single assertion, on failure returning the lines that explain why.
+pub fn test_case(response: &Response, case: &TestCase) -> Result<(), Vec<String>> {
+    let values = resolve_values(response, &case.key);
+
+    let actual = if case.count {
+        values.len().to_string()
+    } else {
+        match case.operation {
+            Operator::Exists | Operator::NotExists => {
+                let found = !values.is_empty();
+                return if found == matches!(case.operation, Operator::Exists) {
+                    Ok(())
+                } else {
+                    Err(vec![format!(
+                        "actual:   {}",
+                        if found { "present" } else { "missing" }
+                    )])
+                };
+            }
+            _ => values.into_iter().next().unwrap_or_default(),
+        }
+    };
+
+    check_value(&actual, case)
+}
+
+/// Points the schema file of a `matches-schema` case into `base_dir`, like every other file
+/// reference of a script.
+pub fn resolve_schema_path(case: &mut TestCase, base_dir: &Path) {
+    if let Operator::MatchesSchema = case.operation {
+        case.value = base_dir.join(&case.value).to_string_lossy().into_owned();
+    }
+}
+
+/// Applies the operator of `case` to a value already picked out of a response or message.
+pub fn check_value(actual: &str, case: &TestCase) -> Result<(), Vec<String>> {
+    if let Operator::MatchesSchema = case.operation {
+        return check_schema(actual, &case.value);
+    }
+
+    if do_operation(&case.operation, actual, &case.value) {
+        Ok(())
+    } else {
+        Err(explain_failure(case, actual))
+    }
+}
+
+const MAX_ACTUAL_LEN: usize = 200;
+
+/// Shows the actual value next to the expected one, as a diff for JSON or multi-line values.
+fn explain_failure(case: &TestCase, actual: &str) -> Vec<String> {
+    let mut details = vec![format!("expected: {} {}", case.operation, case.value)];
+
+    if matches!(
+        case.operation,
+        Operator::Equals | Operator::EqualsIgnoreCase
+    ) {
+        if let (Ok(expected_json), Ok(actual_json)) = (
+            serde_json::from_str::<Value>(&case.value),
+            serde_json::from_str::<Value>(actual),
+        ) && (expected_json.is_object() || expected_json.is_array())
+        {
+            let diff = json_diff(&expected_json, &actual_json);
+            if diff.is_empty() {
+                details.push("actual:   same JSON, formatted differently".to_string());
+            } else {
+                details.extend(diff);
+            }
+            return details;
+        }
+
+        if actual.lines().count() > 1 {
+            details.extend(line_diff(&case.value, actual));
+            return details;
+        }
     }
 
-    let header_val = response.get_header(&case.key).unwrap_or("");
-    do_operation(&case.operation, header_val, &case.value)
+    // Multi-line JSON bodies read better compacted onto the single actual line
+    let compact = serde_json::from_str::<Value>(actual)
+        .map(|json| json.to_string())
+        .unwrap_or_else(|_| actual.replace('\n', " "));
+    let shown = match compact.char_indices().nth(MAX_ACTUAL_LEN) {
+        Some((cut, _)) => format!(
+            "{}... ({} more chars)",
+            &compact[..cut],
+            compact[cut..].chars().count()
+        ),
+        None => compact,
+    };
+    details.push(format!("actual:   {}", shown));
+    details
+}
+
+fn check_schema(actual: &str, schema_path: &str) -> Result<(), Vec<String>> {
+    let schema = load_schema(schema_path).map_err(|e| vec![e])?;
+    let instance: Value =
+        serde_json::from_str(actual).map_err(|e| vec![format!("Value is not JSON: {}", e)])?;
+
+    let violations = validate(&schema, &instance);
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations.iter().map(|v| v.to_string()).collect())
+    }
 }
 
 pub fn get_operator(op_str: &str) -> Result<Operator, String> {
@@ -71,18 +351,99 @@ pub fn get_operator(op_str: &str) -> Result<Operator, String> {
         "<" => Ok(Operator::SmallerThan),
         "<=" => Ok(Operator::SmallerThanOrEqualTo),
         "~" => Ok(Operator::Contains),
+        "!~" => Ok(Operator::NotContains),
         "^" => Ok(Operator::StartsWith),
+        "$" => Ok(Operator::EndsWith),
+        "=~" => Ok(Operator::Matches),
+        "in" => Ok(Operator::In),
+        "=*" => Ok(Operator::EqualsIgnoreCase),
+        "!=*" => Ok(Operator::NotEqualsIgnoreCase),
+        "~*" => Ok(Operator::ContainsIgnoreCase),
+        "!~*" => Ok(Operator::NotContainsIgnoreCase),
+        "^*" => Ok(Operator::StartsWithIgnoreCase),
+        "$*" => Ok(Operator::EndsWithIgnoreCase),
+        "=~*" => Ok(Operator::MatchesIgnoreCase),
+        "in*" => Ok(Operator::InIgnoreCase),
+        "matches-schema" => Ok(Operator::MatchesSchema),
+        "exists" => Ok(Operator::Exists),
+        "!exists" => Ok(Operator::NotExists),
         invalid => Err(format!("Syntax error, unknown symbol, {}", invalid)),
     }
 }
 
+/// Checks assertions that can be rejected before any request is sent.
+pub fn validate_test_case(case: &TestCase) -> Result<(), String> {
+    let (name, _) = parse_header_key(&case.key)?;
+    if let Some(field) = name.strip_prefix("event.")
+        && !matches!(field, "data" | "type" | "id" | "retry")
+    {
+        return Err(format!(
+            "Unknown event field `{}`, expected data, type, id or retry",
+            field
+        ));
+    }
+
+    let is_unary = matches!(case.operation, Operator::Exists | Operator::NotExists);
+    if is_unary && (case.count || !case.value.is_empty()) {
+        return Err(format!(
+            "`{}` takes no value, found {}",
+            case.operation, case
+        ));
+    } else if !is_unary && case.value.is_empty() {
+        return Err(format!("Missing value in assertion, {}", case));
+    }
+
+    let right = &case.value;
+    match case.operation {
+        Operator::Matches | Operator::MatchesIgnoreCase => RegexBuilder::new(right)
+            .build()
+            .map(|_| ())
+            .map_err(|e| format!("Invalid regex `{}`: {}", right, e)),
+        Operator::In | Operator::InIgnoreCase => parse_list(right).map(|_| ()),
+        Operator::MatchesSchema => load_schema(right).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// Parses a `[a, b, c]` operand into its items.
+fn parse_list(value: &str) -> Result<Vec<&str>, String> {
+    let inner = value
+        .strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .ok_or(format!("Expected a list like [a, b, c], found {}", value))?;
+
+    Ok(inner
+        .split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .collect())
+}
+
 pub fn do_operation(operation: &Operator, left: &str, right: &str) -> bool {
     match operation {
-        Operator::Unknown => false,
+        Operator::Unknown | Operator::MatchesSchema | Operator::Exists | Operator::NotExists => {
+            false
+        }
         Operator::Equals => left == right,
         Operator::NotEquals => left != right,
         Operator::Contains => left.contains(right),
+        Operator::NotContains => !left.contains(right),
         Operator::StartsWith => left.starts_with(right),
+        Operator::EndsWith => left.ends_with(right),
+        Operator::In => parse_list(right).is_ok_and(|items| items.contains(&left)),
+        op @ (Operator::Matches | Operator::MatchesIgnoreCase) => RegexBuilder::new(right)
+            .case_insensitive(matches!(op, Operator::MatchesIgnoreCase))
+            .build()
+            .is_ok_and(|re| re.is_match(left)),
+        op @ (Operator::EqualsIgnoreCase
+        | Operator::NotEqualsIgnoreCase
+        | Operator::ContainsIgnoreCase
+        | Operator::NotContainsIgnoreCase
+        | Operator::StartsWithIgnoreCase
+        | Operator::EndsWithIgnoreCase
+        | Operator::InIgnoreCase) => op.case_sensitive().is_some_and(|sensitive| {
+            do_operation(&sensitive, &left.to_lowercase(), &right.to_lowercase())
+        }),
         op @ (Operator::GreaterThan
         | Operator::SmallerThan
         | Operator::GreaterThanOrEqualTo
@@ -104,3 +465,245 @@ pub fn do_operation(operation: &Operator, left: &str, right: &str) -> bool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::http::Timings;
+
+    fn case(key: &str, operation: &str, value: &str) -> TestCase {
+        TestCase {
+            key: key.to_string(),
+            value: value.to_string(),
+            operation: get_operator(operation).unwrap(),
+            count: false,
+        }
+    }
+
+    #[test]
+    fn negated_and_case_insensitive_operators() {
+        assert!(do_operation(&Operator::NotEquals, "a", "b"));
+        assert!(!do_operation(&Operator::NotContains, "abc", "b"));
+        assert!(do_operation(&Operator::EqualsIgnoreCase, "JSON", "json"));
+        assert!(!do_operation(
+            &Operator::NotEqualsIgnoreCase,
+            "JSON",
+            "json"
+        ));
+        assert!(do_operation(&Operator::ContainsIgnoreCase, "Hello", "ELL"));
+        assert!(do_operation(&Operator::NotContainsIgnoreCase, "Hello", "x"));
+        assert!(do_operation(
+            &Operator::StartsWithIgnoreCase,
+            "Bearer x",
+            "bearer"
+        ));
+        assert!(do_operation(
+            &Operator::EndsWithIgnoreCase,
+            "a.JSON",
+            ".json"
+        ));
+    }
+
+    #[test]
+    fn regex_operators() {
+        assert!(do_operation(&Operator::Matches, "id-42", r"^id-\d+$"));
+        assert!(!do_operation(&Operator::Matches, "ID-42", r"^id-\d+$"));
+        assert!(do_operation(
+            &Operator::MatchesIgnoreCase,
+            "ID-42",
+            r"^id-\d+$"
+        ));
+        // An invalid pattern never matches
+        assert!(!do_operation(&Operator::Matches, "(", "("));
+    }
+
+    #[test]
+    fn list_operators() {
+        assert!(do_operation(&Operator::In, "200", "[200, 201, 204]"));
+        assert!(!do_operation(&Operator::In, "20", "[200, 201]"));
+        assert!(!do_operation(&Operator::In, "200", "200"));
+        assert!(do_operation(&Operator::InIgnoreCase, "OK", "[ok, created]"));
+    }
+
+    #[test]
+    fn numeric_operators() {
+        assert!(do_operation(&Operator::GreaterThan, "10", "9"));
+        assert!(do_operation(&Operator::SmallerThanOrEqualTo, "1.5", "1.5"));
+        assert!(!do_operation(&Operator::GreaterThan, "ten", "9"));
+    }
+
+    #[test]
+    fn validates_operands_up_front() {
+        assert!(validate_test_case(&case("data.id", "=~", r"^\d+$")).is_ok());
+        assert!(validate_test_case(&case("data.id", "in", "[1, 2]")).is_ok());
+        assert!(validate_test_case(&case("header.x-id", "exists", "")).is_ok());
+        assert!(
+            validate_test_case(&case("data.id", "=~*", "(unclosed"))
+                .unwrap_err()
+                .starts_with("Invalid regex `(unclosed`")
+        );
+        assert_eq!(
+            validate_test_case(&case("data.id", "in", "1, 2")),
+            Err(String::from("Expected a list like [a, b, c], found 1, 2"))
+        );
+        assert_eq!(
+            validate_test_case(&case("data.id", "!=", "")),
+            Err(String::from("Missing value in assertion, data.id !="))
+        );
+        assert!(validate_test_case(&case("data.id", "!exists", "1")).is_err());
+        assert!(validate_test_case(&case("header.x[a]", "=", "1")).is_err());
+        assert!(validate_test_case(&case("event.name", "=", "1")).is_err());
+    }
+
+    #[test]
+    fn resolves_rpc_results_by_call_position() {
+        let mut response = Response::new("HTTP/1.1", 200, Vec::new());
+        response.rpc_results = vec![serde_json::json!({"user": {"name": "ada"}})];
+        assert_eq!(resolve_values(&response, "result.user.name"), ["ada"]);
+        assert_eq!(
+            resolve_values(&response, "result"),
+            [r#"{"user":{"name":"ada"}}"#]
+        );
+
+        response.rpc_results = vec![serde_json::json!(3), Value::Null, serde_json::json!([1, 2])];
+        assert_eq!(resolve_values(&response, "result.0"), ["3"]);
+        assert_eq!(resolve_values(&response, "result.1"), ["null"]);
+        assert_eq!(resolve_values(&response, "result.2.1"), ["2"]);
+        assert!(resolve_values(&response, "result.3").is_empty());
+    }
+
+    fn headers_response() -> Response {
+        Response::new(
+            "HTTP/1.1",
+            200,
+            vec![
+                ("Set-Cookie".to_string(), "a=1".to_string()),
+                ("Content-Type".to_string(), "text/plain".to_string()),
+                ("set-cookie".to_string(), "b=2".to_string()),
+            ],
+        )
+    }
+
+    #[test]
+    fn resolves_repeated_headers_by_index() {
+        let response = headers_response();
+        assert_eq!(
+            resolve_values(&response, "header.set-cookie"),
+            ["a=1", "b=2"]
+        );
+        assert_eq!(resolve_values(&response, "header.Set-Cookie[1]"), ["b=2"]);
+        assert!(resolve_values(&response, "header.set-cookie[2]").is_empty());
+        assert_eq!(resolve_values(&response, "content-type"), ["text/plain"]);
+        assert!(resolve_values(&response, "header.set-cookie[x]").is_empty());
+    }
+
+    #[test]
+    fn counts_and_checks_presence() {
+        let response = headers_response();
+        let count = TestCase {
+            count: true,
+            ..case("header.set-cookie", "=", "2")
+        };
+        assert_eq!(test_case(&response, &count), Ok(()));
+        assert!(
+            test_case(
+                &response,
+                &TestCase {
+                    count: true,
+                    ..case("x-id", "=", "0")
+                }
+            )
+            .is_ok()
+        );
+
+        assert_eq!(
+            test_case(&response, &case("header.set-cookie[1]", "exists", "")),
+            Ok(())
+        );
+        assert_eq!(
+            test_case(&response, &case("header.set-cookie[2]", "!exists", "")),
+            Ok(())
+        );
+        assert_eq!(
+            test_case(&response, &case("header.x-id", "exists", "")),
+            Err(vec!["actual:   missing".to_string()])
+        );
+    }
+
+    #[test]
+    fn resolves_timings_in_milliseconds() {
+        let mut response = Response::new("HTTP/1.1", 200, Vec::new());
+        response.timings = Timings {
+            dns: Duration::from_millis(2),
+            connect: Duration::from_millis(3),
+            tls: Duration::ZERO,
+            ttfb: Duration::from_micros(12_500),
+            download: Duration::from_millis(1),
+        };
+        assert_eq!(resolve_values(&response, "dns"), ["2"]);
+        assert_eq!(resolve_values(&response, "ttfb"), ["12.5"]);
+        assert_eq!(resolve_values(&response, "time"), ["18.5"]);
+        assert!(test_case(&response, &case("time", "<", "20")).is_ok());
+    }
+
+    #[test]
+    fn resolves_event_fields() {
+        let mut response = Response::new("HTTP/1.1", 200, Vec::new());
+        response.events = vec![
+            SseEvent {
+                event: "message".to_string(),
+                data: "first".to_string(),
+                id: Some("1".to_string()),
+                retry: None,
+            },
+            SseEvent {
+                event: "done".to_string(),
+                data: "{\"ok\":true}".to_string(),
+                id: None,
+                retry: Some(500),
+            },
+        ];
+        assert_eq!(
+            resolve_values(&response, "event.data"),
+            ["first", "{\"ok\":true}"]
+        );
+        assert_eq!(resolve_values(&response, "event.type[1]"), ["done"]);
+        assert_eq!(resolve_values(&response, "event.id"), ["1"]);
+        assert_eq!(resolve_values(&response, "event.retry[1]"), ["500"]);
+        assert!(resolve_values(&response, "event.retry[0]").is_empty());
+        assert!(resolve_values(&response, "event.data[2]").is_empty());
+        assert!(
+            test_case(
+                &response,
+                &TestCase {
+                    count: true,
+                    ..case("event.data", "=", "2")
+                }
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn resolves_json_paths() {
+        let mut response = Response::new("HTTP/1.1", 200, Vec::new());
+        response.body =
+            r#"{"data": {"users": [{"name": "ada", "age": 36}, {"name": "alan"}]}}"#.to_string();
+        assert_eq!(resolve_values(&response, "data.users.0.name"), ["ada"]);
+        assert_eq!(
+            resolve_values(&response, "data.users.*.name"),
+            ["ada", "alan"]
+        );
+        assert_eq!(resolve_values(&response, "data.users.0.age"), ["36"]);
+        assert_eq!(
+            resolve_values(&response, "data.users.1"),
+            [r#"{"name":"alan"}"#]
+        );
+        assert!(resolve_values(&response, "data.users.2").is_empty());
+
+        response.body = "not json".to_string();
+        assert!(resolve_values(&response, "data.users").is_empty());
+    }
+}
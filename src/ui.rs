@@ -1,19 +1,86 @@
 use std::{
+    env,
     error::Error,
-    io::Write,
-    process::{Command, Stdio},
+    io::{self, ErrorKind, IsTerminal, Write},
+    process::{Child, ChildStdin, Command, Stdio},
 };
 
-pub fn view_in_less(content: &str) -> Result<(), Box<dyn Error>> {
-    let mut less = Command::new("less")
-        .stdin(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("Failed to spawn less: {}", e))?;
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+fn use_color() -> bool {
+    io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none()
+}
+
+/// Colors removed (`- `), added (`+ `) and changed (`~ `) diff lines when printing to a terminal.
+pub fn paint_diff_line(line: &str) -> String {
+    let color = match line.get(..2) {
+        Some("- ") => RED,
+        Some("+ ") => GREEN,
+        Some("~ ") => YELLOW,
+        _ => return line.to_string(),
+    };
+
+    if use_color() {
+        format!("{}{}{}", color, line, RESET)
+    } else {
+        line.to_string()
+    }
+}
+
+/// A running `less` fed through its stdin, so output can be shown while it is still produced.
+pub struct Pager {
+    less: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl Pager {
+    pub fn open() -> Result<Pager, String> {
+        let mut less = Command::new("less")
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to spawn less: {}", e))?;
+        let stdin = less.stdin.take();
 
-    if let Some(mut stdin) = less.stdin.take() {
-        stdin.write_all(content.as_bytes())?;
+        Ok(Pager { less, stdin })
     }
 
-    less.wait()?;
+    /// Closes the input and waits for the user to quit.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.stdin = None;
+        self.less.wait().map(|_| ())
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(stdin) = self.stdin.as_mut() else {
+            return Ok(buf.len());
+        };
+
+        match stdin.write(buf) {
+            // Quitting less early is not an error, the rest is thrown away
+            Err(err) if err.kind() == ErrorKind::BrokenPipe => {
+                self.stdin = None;
+                Ok(buf.len())
+            }
+            result => result,
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn view_in_less(content: &str) -> Result<(), Box<dyn Error>> {
+    let mut pager = Pager::open()?;
+    pager.write_all(content.as_bytes())?;
+    pager.finish()?;
     Ok(())
 }
@@ -0,0 +1,23 @@
+use std::env;
+
+/// Replaces every `{{NAME}}` in `text` with the `NAME` environment variable.
+pub fn substitute(text: &str) -> Result<String, String> {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or(format!("Unclosed variable in `{}`", text))?;
+
+        let name = after[..end].trim();
+        let value = env::var(name).map_err(|_| format!("Variable `{}` is not set", name))?;
+        output.push_str(&value);
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
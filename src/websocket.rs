@@ -0,0 +1,799 @@
+use std::{
+    fmt,
+    io::{self, BufReader, ErrorKind, Read, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use sha1_smol::Sha1;
+
+use crate::{
+    http::{Response, Timings, parse_head, read_head},
+    network::{ReadTimeout, Stream},
+    test_bed::{
+        Operator, TestCase, check_value, get_operator, resolve_schema_path, validate_test_case,
+    },
+    util::{from_hex, random_bytes, to_hex},
+};
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// How long an `expect` or `close` step waits for the server.
+const EXPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageKind {
+    Text,
+    Binary,
+    Ping,
+    Pong,
+    Close,
+}
+
+impl MessageKind {
+    pub fn parse(kind: &str) -> Option<MessageKind> {
+        match kind {
+            "text" => Some(MessageKind::Text),
+            "binary" => Some(MessageKind::Binary),
+            "ping" => Some(MessageKind::Ping),
+            "pong" => Some(MessageKind::Pong),
+            "close" => Some(MessageKind::Close),
+            _ => None,
+        }
+    }
+
+    fn opcode(&self) -> u8 {
+        match self {
+            MessageKind::Text => OPCODE_TEXT,
+            MessageKind::Binary => OPCODE_BINARY,
+            MessageKind::Ping => OPCODE_PING,
+            MessageKind::Pong => OPCODE_PONG,
+            MessageKind::Close => OPCODE_CLOSE,
+        }
+    }
+}
+
+impl fmt::Display for MessageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            MessageKind::Text => "text",
+            MessageKind::Binary => "binary",
+            MessageKind::Ping => "ping",
+            MessageKind::Pong => "pong",
+            MessageKind::Close => "close",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+#[derive(Debug)]
+pub struct Message {
+    pub kind: MessageKind,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    /// The payload as assertions see it: text as is, binary as hex and close frames as their code.
+    pub fn value(&self) -> String {
+        match self.kind {
+            MessageKind::Binary => to_hex(&self.payload),
+            MessageKind::Close => match self.payload.get(..2) {
+                Some(code) => u16::from_be_bytes([code[0], code[1]]).to_string(),
+                None => String::new(),
+            },
+            _ => String::from_utf8_lossy(&self.payload).into_owned(),
+        }
+    }
+}
+
+/// One line of a `<websocket>` block.
+#[derive(Debug)]
+pub enum Step {
+    Send(Message),
+    /// Waits for the next message of `kind`, checking its value when `case` is set.
+    Expect {
+        kind: MessageKind,
+        case: Option<TestCase>,
+    },
+    Close(u16),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Send(message) => write!(f, "send {} {}", message.kind, message.value()),
+            Step::Expect {
+                case: Some(case), ..
+            } => write!(f, "expect {}", case),
+            Step::Expect { kind, case: None } => write!(f, "expect {}", kind),
+            Step::Close(code) => write!(f, "close {}", code),
+        }
+    }
+}
+
+/// Parses `send <kind> <payload>`, `expect <kind> [<operator> <value>]` or `close [<code>]`,
+/// schema files being resolved against `base_dir`.
+pub fn parse_step(line: &str, base_dir: &Path) -> Result<Step, String> {
+    let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let (kind_name, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    let kind = || {
+        MessageKind::parse(kind_name).ok_or(format!(
+            "Unknown message kind `{}`, expected text, binary, ping, pong or close",
+            kind_name
+        ))
+    };
+
+    match command {
+        "send" => Ok(Step::Send(parse_send(kind()?, rest)?)),
+        "expect" => {
+            let kind = kind()?;
+            if rest.is_empty() {
+                return Ok(Step::Expect { kind, case: None });
+            }
+
+            let (operator, value) = rest.split_once(' ').unwrap_or((rest, ""));
+            let mut case = TestCase {
+                key: kind_name.to_string(),
+                value: value.to_string(),
+                operation: get_operator(operator)?,
+                count: false,
+            };
+            if matches!(case.operation, Operator::Exists | Operator::NotExists) {
+                return Err(format!("`{}` does not apply to messages", case.operation));
+            }
+            resolve_schema_path(&mut case, base_dir);
+            validate_test_case(&case)?;
+            Ok(Step::Expect {
+                kind,
+                case: Some(case),
+            })
+        }
+        "close" if rest.is_empty() => match kind_name {
+            "" => Ok(Step::Close(1000)),
+            code => code
+                .parse()
+                .map(Step::Close)
+                .map_err(|_| format!("Invalid close code: {}", code)),
+        },
+        _ => Err(format!("Invalid websocket step, {}", line)),
+    }
+}
+
+/// Parses the payload of a `send` line, binary payloads being written in hex.
+fn parse_send(kind: MessageKind, payload: &str) -> Result<Message, String> {
+    let payload = match kind {
+        MessageKind::Text | MessageKind::Ping | MessageKind::Pong => payload.as_bytes().to_vec(),
+        MessageKind::Binary => from_hex(payload)?,
+        MessageKind::Close => return Err("Use `close <code>` to close the session".to_string()),
+    };
+    Ok(Message { kind, payload })
+}
+
+/// Sends the HTTP Upgrade request along with `headers` and checks the accept key when the
+/// server switched protocols. Any other response is returned with its body left unread.
+pub fn upgrade<S: Read + Write>(
+    reader: &mut BufReader<S>,
+    host: &str,
+    url: &str,
+    headers: &[(String, String)],
+    timings: &mut Timings,
+) -> Result<Response, String> {
+    let key = STANDARD.encode(random_bytes::<16>());
+    let mut request = format!(
+        "GET {url} HTTP/1.1\r\n\
+        Host: {host}\r\n\
+        User-Agent: NetHop/0.0\r\n\
+        Upgrade: websocket\r\n\
+        Connection: Upgrade\r\n\
+        Sec-WebSocket-Key: {key}\r\n\
+        Sec-WebSocket-Version: 13\r\n"
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+
+    let started = Instant::now();
+    reader
+        .get_mut()
+        .write_all(request.as_bytes())
+        .and_then(|_| reader.get_mut().flush())
+        .map_err(|err| format!("Failed to send request: {}", err))?;
+
+    let (head, _) = read_head(reader, started, timings)?;
+    let mut response = parse_head(&head)?;
+    response.timings = *timings;
+    if response.status != 101 {
+        return Ok(response);
+    }
+
+    if response.get_header("sec-websocket-accept") != Some(accept_key(&key).as_str()) {
+        return Err("Server sent a wrong Sec-WebSocket-Accept".to_string());
+    }
+
+    Ok(response)
+}
+
+/// The `Sec-WebSocket-Accept` a server answers `key` with.
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(ACCEPT_GUID.as_bytes());
+    STANDARD.encode(hasher.digest().bytes())
+}
+
+/// An upgraded connection playing the steps of a `<websocket>` block.
+pub struct Session<'a, S = Stream> {
+    reader: &'a mut BufReader<S>,
+    /// A fragmented message still missing its last frame.
+    partial: Option<Message>,
+    closed: bool,
+}
+
+impl<'a, S: Read + Write + ReadTimeout> Session<'a, S> {
+    pub fn new(reader: &'a mut BufReader<S>) -> Self {
+        Session {
+            reader,
+            partial: None,
+            closed: false,
+        }
+    }
+
+    /// Runs one step, on failure returning the lines that explain why.
+    pub fn run(&mut self, step: &Step) -> Result<(), Vec<String>> {
+        match step {
+            Step::Send(message) => self
+                .send(message.kind.opcode(), &message.payload)
+                .map_err(|err| vec![format!("Failed to send: {}", err)]),
+            Step::Expect { kind, case } => {
+                let message = self.receive(*kind)?;
+                if message.kind != *kind {
+                    return Err(vec![
+                        format!("expected: {} message", kind),
+                        format!("actual:   {} message {}", message.kind, message.value()),
+                    ]);
+                }
+                match case {
+                    Some(case) => check_value(&message.value(), case),
+                    None => Ok(()),
+                }
+            }
+            Step::Close(code) => {
+                self.send(OPCODE_CLOSE, &code.to_be_bytes())
+                    .map_err(|err| vec![format!("Failed to send: {}", err)])?;
+                loop {
+                    if self.receive(MessageKind::Close)?.kind == MessageKind::Close {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Says goodbye if no step did, without waiting for the answer.
+    pub fn finish(mut self) {
+        if !self.closed {
+            let _ = self.send(OPCODE_CLOSE, &1000u16.to_be_bytes());
+        }
+    }
+
+    fn send(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        // Client frames are always masked
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(0x80 | 126);
+                frame.extend((len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend((len as u64).to_be_bytes());
+            }
+        }
+        let mask = random_bytes::<4>();
+        frame.extend(mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+
+        if opcode == OPCODE_CLOSE {
+            self.closed = true;
+        }
+        let stream = self.reader.get_mut();
+        stream.write_all(&frame)?;
+        stream.flush()
+    }
+
+    /// Reads the next message, answering pings along the way unless a ping is `expected`.
+    fn receive(&mut self, expected: MessageKind) -> Result<Message, Vec<String>> {
+        let failed = |err: io::Error| {
+            if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+                vec![format!(
+                    "No {} message within {}s",
+                    expected,
+                    EXPECT_TIMEOUT.as_secs()
+                )]
+            } else {
+                vec![format!("Failed to read message: {}", err)]
+            }
+        };
+
+        let stream = self.reader.get_ref();
+        stream
+            .set_read_timeout(Some(EXPECT_TIMEOUT))
+            .map_err(failed)?;
+        let message = loop {
+            let message = self.read_message().map_err(failed)?;
+            if message.kind == MessageKind::Ping && expected != MessageKind::Ping {
+                self.send(OPCODE_PONG, &message.payload).map_err(failed)?;
+                continue;
+            }
+            break message;
+        };
+        let _ = self.reader.get_ref().set_read_timeout(None);
+
+        if message.kind == MessageKind::Close && !self.closed {
+            let _ = self.send(OPCODE_CLOSE, &message.payload);
+        }
+        Ok(message)
+    }
+
+    /// Reads frames until a whole message is in, joining fragments of text and binary messages.
+    fn read_message(&mut self) -> io::Result<Message> {
+        loop {
+            let (fin, opcode, payload) = self.read_frame()?;
+            let kind = match opcode {
+                OPCODE_CONTINUATION => {
+                    let Some(mut partial) = self.partial.take() else {
+                        return Err(self.fail("Continuation frame without a message"));
+                    };
+                    partial.payload.extend(payload);
+                    if fin {
+                        return Ok(partial);
+                    }
+                    self.partial = Some(partial);
+                    continue;
+                }
+                OPCODE_TEXT => MessageKind::Text,
+                OPCODE_BINARY => MessageKind::Binary,
+                OPCODE_CLOSE => MessageKind::Close,
+                OPCODE_PING => MessageKind::Ping,
+                OPCODE_PONG => MessageKind::Pong,
+                other => return Err(self.fail(&format!("Unknown opcode {:#x}", other))),
+            };
+            if self.partial.is_some() && matches!(kind, MessageKind::Text | MessageKind::Binary) {
+                return Err(self.fail("New message before the fragmented one ended"));
+            }
+
+            let frame = Message { kind, payload };
+            // Control frames may arrive between the fragments of a message
+            if fin
+                || matches!(
+                    kind,
+                    MessageKind::Close | MessageKind::Ping | MessageKind::Pong
+                )
+            {
+                return Ok(frame);
+            }
+            self.partial = Some(frame);
+        }
+    }
+
+    /// Fails the connection over a protocol error, closing it with code 1002 (RFC 6455, 7.1.7).
+    fn fail(&mut self, reason: &str) -> io::Error {
+        self.partial = None;
+        let _ = self.send(OPCODE_CLOSE, &1002u16.to_be_bytes());
+        io::Error::new(ErrorKind::InvalidData, reason)
+    }
+
+    fn read_frame(&mut self) -> io::Result<(bool, u8, Vec<u8>)> {
+        let mut head = [0u8; 2];
+        self.reader.read_exact(&mut head)?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        let masked = head[1] & 0x80 != 0;
+
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0u8; 2];
+                self.reader.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0u8; 8];
+                self.reader.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+
+        let mut mask = [0u8; 4];
+        if masked {
+            self.reader.read_exact(&mut mask)?;
+        }
+
+        let mut payload = Vec::new();
+        self.reader.by_ref().take(len).read_to_end(&mut payload)?;
+        if (payload.len() as u64) < len {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        if masked {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+
+        Ok((fin, opcode, payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, io::Cursor};
+
+    use super::*;
+
+    /// A server sending `input`, or what `respond` makes of the request once it is read.
+    #[derive(Default)]
+    struct Peer {
+        input: Cursor<Vec<u8>>,
+        respond: Option<fn(&str) -> String>,
+        written: Vec<u8>,
+        timeout: Cell<Option<Duration>>,
+    }
+
+    impl Read for Peer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if let Some(respond) = self.respond.take() {
+                let response = respond(&String::from_utf8_lossy(&self.written));
+                self.input = Cursor::new(response.into_bytes());
+            }
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Peer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ReadTimeout for Peer {
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.timeout.set(timeout);
+            Ok(())
+        }
+    }
+
+    /// An unmasked frame as a server sends it.
+    fn frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(126);
+                frame.extend((len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend((len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// The frames a client wrote, unmasked, checking every one of them was masked.
+    fn client_frames(mut written: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut frames = Vec::new();
+        while !written.is_empty() {
+            assert_eq!(written[0] & 0x80, 0x80, "fin");
+            assert_eq!(written[1] & 0x80, 0x80, "mask");
+            let (len, start) = match written[1] & 0x7F {
+                126 => (u16::from_be_bytes([written[2], written[3]]) as usize, 4),
+                127 => (
+                    u64::from_be_bytes(written[2..10].try_into().unwrap()) as usize,
+                    10,
+                ),
+                len => (len as usize, 2),
+            };
+            let mask = &written[start..start + 4];
+            let payload = written[start + 4..start + 4 + len]
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4])
+                .collect();
+            frames.push((written[0] & 0x0F, payload));
+            written = &written[start + 4 + len..];
+        }
+        frames
+    }
+
+    fn peer(frames: &[Vec<u8>]) -> BufReader<Peer> {
+        BufReader::new(Peer {
+            input: Cursor::new(frames.concat()),
+            ..Default::default()
+        })
+    }
+
+    fn step(line: &str) -> Step {
+        parse_step(line, Path::new("")).unwrap()
+    }
+
+    #[test]
+    fn masks_frames_with_each_length_form() {
+        for (len, header_len) in [(0, 2), (125, 2), (126, 4), (65_535, 4), (65_536, 10)] {
+            let mut reader = peer(&[]);
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            Session::new(&mut reader)
+                .send(OPCODE_BINARY, &payload)
+                .unwrap();
+
+            let written = &reader.get_ref().written;
+            assert_eq!(written.len(), header_len + 4 + len);
+            assert_eq!(written[0], 0x82);
+            assert_eq!(client_frames(written), [(OPCODE_BINARY, payload)]);
+        }
+    }
+
+    #[test]
+    fn reads_each_length_form() {
+        for len in [0, 125, 126, 65_535, 70_000] {
+            let payload = vec![b'x'; len];
+            let mut reader = peer(&[frame(true, OPCODE_TEXT, &payload)]);
+            let message = Session::new(&mut reader).read_message().unwrap();
+            assert_eq!(message.kind, MessageKind::Text);
+            assert_eq!(message.payload, payload);
+        }
+    }
+
+    #[test]
+    fn joins_fragments_around_control_frames() {
+        let mut reader = peer(&[
+            frame(false, OPCODE_TEXT, b"Hel"),
+            frame(true, OPCODE_PING, b"are you there"),
+            frame(false, OPCODE_CONTINUATION, b"lo "),
+            frame(true, OPCODE_PING, b""),
+            frame(true, OPCODE_CONTINUATION, b"world"),
+        ]);
+        let mut session = Session::new(&mut reader);
+        assert!(session.run(&step("expect text = Hello world")).is_ok());
+        drop(session);
+
+        let peer = reader.get_ref();
+        assert_eq!(
+            client_frames(&peer.written),
+            [
+                (OPCODE_PONG, b"are you there".to_vec()),
+                (OPCODE_PONG, Vec::new())
+            ]
+        );
+        assert_eq!(peer.timeout.get(), None);
+    }
+
+    #[test]
+    fn hands_expected_pings_over() {
+        let mut reader = peer(&[frame(true, OPCODE_PING, b"hi")]);
+        assert!(
+            Session::new(&mut reader)
+                .run(&step("expect ping = hi"))
+                .is_ok()
+        );
+        assert!(reader.get_ref().written.is_empty());
+    }
+
+    #[test]
+    fn fails_on_interrupted_fragments() {
+        let mut reader = peer(&[
+            frame(false, OPCODE_TEXT, b"a"),
+            frame(true, OPCODE_BINARY, b"b"),
+        ]);
+        let mut session = Session::new(&mut reader);
+        let error = session.read_message().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            error.to_string(),
+            "New message before the fragmented one ended"
+        );
+        assert!(session.closed);
+        assert_eq!(
+            client_frames(&reader.get_ref().written),
+            [(OPCODE_CLOSE, 1002u16.to_be_bytes().to_vec())]
+        );
+
+        for frames in [
+            [frame(true, OPCODE_CONTINUATION, b"a")],
+            [frame(true, 0x3, b"a")],
+        ] {
+            let mut reader = peer(&frames);
+            assert!(Session::new(&mut reader).read_message().is_err());
+            assert_eq!(
+                client_frames(&reader.get_ref().written),
+                [(OPCODE_CLOSE, 1002u16.to_be_bytes().to_vec())]
+            );
+        }
+    }
+
+    #[test]
+    fn runs_steps() {
+        let mut reader = peer(&[
+            frame(true, OPCODE_TEXT, b"welcome"),
+            frame(true, OPCODE_BINARY, &[0xca, 0xfe]),
+            frame(true, OPCODE_CLOSE, &1000u16.to_be_bytes()),
+        ]);
+        let mut session = Session::new(&mut reader);
+        assert!(session.run(&step("send text hi")).is_ok());
+        assert!(session.run(&step("expect text ^ wel")).is_ok());
+        assert_eq!(
+            session.run(&step("expect text = x")),
+            Err(vec![
+                String::from("expected: text message"),
+                String::from("actual:   binary message cafe")
+            ])
+        );
+        assert!(session.run(&step("close")).is_ok());
+        session.finish();
+
+        assert_eq!(
+            client_frames(&reader.get_ref().written),
+            [
+                (OPCODE_TEXT, b"hi".to_vec()),
+                (OPCODE_CLOSE, 1000u16.to_be_bytes().to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn answers_the_server_closing() {
+        let mut reader = peer(&[frame(true, OPCODE_CLOSE, &1001u16.to_be_bytes())]);
+        let mut session = Session::new(&mut reader);
+        assert!(session.run(&step("expect close = 1001")).is_ok());
+        session.finish();
+        assert_eq!(
+            client_frames(&reader.get_ref().written),
+            [(OPCODE_CLOSE, 1001u16.to_be_bytes().to_vec())]
+        );
+    }
+
+    #[test]
+    fn reports_a_silent_server() {
+        let mut reader = peer(&[]);
+        assert_eq!(
+            Session::new(&mut reader).run(&step("expect text")),
+            Err(vec![String::from(
+                "Failed to read message: failed to fill whole buffer"
+            )])
+        );
+    }
+
+    #[test]
+    fn parses_steps() {
+        let Step::Send(message) = step("send text hello world") else {
+            panic!("expected a send step");
+        };
+        assert_eq!(message.kind, MessageKind::Text);
+        assert_eq!(message.payload, b"hello world");
+        let Step::Send(message) = step("send binary ca fe") else {
+            panic!("expected a send step");
+        };
+        assert_eq!(message.payload, [0xca, 0xfe]);
+        assert!(matches!(
+            step("send ping"),
+            Step::Send(Message {
+                kind: MessageKind::Ping,
+                ..
+            })
+        ));
+        assert!(matches!(
+            step("expect pong"),
+            Step::Expect {
+                kind: MessageKind::Pong,
+                case: None
+            }
+        ));
+        let Step::Expect {
+            kind: MessageKind::Binary,
+            case: Some(case),
+        } = step("expect binary ~* CAFE")
+        else {
+            panic!("expected an expect step");
+        };
+        assert_eq!(case.key, "binary");
+        assert_eq!(case.value, "CAFE");
+        assert!(matches!(step("close"), Step::Close(1000)));
+        assert!(matches!(step("close 4000"), Step::Close(4000)));
+        assert_eq!(step("expect text =~ ^a").to_string(), "expect text =~ ^a");
+    }
+
+    #[test]
+    fn rejects_bad_steps() {
+        let error = |line: &str| parse_step(line, Path::new("")).unwrap_err();
+        assert_eq!(
+            error("send json {}"),
+            "Unknown message kind `json`, expected text, binary, ping, pong or close"
+        );
+        assert_eq!(error("send binary abc"), "Odd number of hex digits in abc");
+        assert_eq!(
+            error("send close"),
+            "Use `close <code>` to close the session"
+        );
+        assert_eq!(
+            error("expect text exists"),
+            "`exists` does not apply to messages"
+        );
+        assert_eq!(
+            error("expect text ?? a"),
+            "Syntax error, unknown symbol, ??"
+        );
+        assert!(error("expect text =~ (").starts_with("Invalid regex"));
+        assert_eq!(error("close soon"), "Invalid close code: soon");
+        assert_eq!(
+            error("close 1000 now"),
+            "Invalid websocket step, close 1000 now"
+        );
+        assert_eq!(error("wait 5"), "Invalid websocket step, wait 5");
+    }
+
+    #[test]
+    fn checks_the_accept_key() {
+        // RFC 6455, section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        let upgrade_with = |respond: fn(&str) -> String| {
+            let mut reader = BufReader::new(Peer {
+                respond: Some(respond),
+                ..Default::default()
+            });
+            let response = upgrade(
+                &mut reader,
+                "example.com:8080",
+                "/chat",
+                &[(String::from("Authorization"), String::from("Bearer t"))],
+                &mut Timings::default(),
+            );
+            (
+                response,
+                String::from_utf8(reader.into_inner().written).unwrap(),
+            )
+        };
+
+        let (response, request) = upgrade_with(|request| {
+            let key = request
+                .lines()
+                .find_map(|line| line.strip_prefix("Sec-WebSocket-Key: "))
+                .unwrap();
+            format!(
+                "HTTP/1.1 101 Switching Protocols\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(key)
+            )
+        });
+        assert_eq!(response.unwrap().status, 101);
+        assert!(request.starts_with("GET /chat HTTP/1.1\r\nHost: example.com:8080\r\n"));
+        assert!(request.ends_with("Sec-WebSocket-Version: 13\r\nAuthorization: Bearer t\r\n\r\n"));
+
+        let (response, _) = upgrade_with(|_| {
+            String::from("HTTP/1.1 101 Switching Protocols\r\nSec-WebSocket-Accept: x\r\n\r\n")
+        });
+        assert_eq!(
+            response.err().as_deref(),
+            Some("Server sent a wrong Sec-WebSocket-Accept")
+        );
+
+        let (response, _) = upgrade_with(|_| String::from("HTTP/1.1 401 Unauthorized\r\n\r\n"));
+        assert_eq!(response.unwrap().status, 401);
+    }
+}